@@ -0,0 +1,3 @@
+mod plaintext_diff;
+
+pub use self::plaintext_diff::PlaintextDiff;
@@ -0,0 +1,195 @@
+use anyhow::anyhow;
+use serde_yaml::Value;
+use similar::TextDiff;
+use std::collections::HashMap;
+
+/// Collection of functions that show how the plaintext of encrypted files
+/// changed, either as a unified diff or key by key for YAML files, optionally
+/// without revealing any values.
+pub struct PlaintextDiff {}
+
+impl PlaintextDiff {
+  /// Returns a unified diff of two plaintexts, empty when they're equal.
+  ///
+  /// # Arguments
+  /// * `old_label` - Name of the old version, shown in the `---` header
+  /// * `old` - Old plaintext
+  /// * `new_label` - Name of the new version, shown in the `+++` header
+  /// * `new` - New plaintext
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::PlaintextDiff;
+  ///
+  /// let diff = PlaintextDiff::unified("a", "api_key: 1\n", "b", "api_key: 2\n");
+  ///
+  /// assert_eq!("--- a\n+++ b\n@@ -1 +1 @@\n-api_key: 1\n+api_key: 2\n", diff);
+  /// ```
+  pub fn unified(old_label: &str, old: &str, new_label: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+      .unified_diff()
+      .header(old_label, new_label)
+      .to_string()
+  }
+
+  /// Returns the keys added (`+`), removed (`-`) and changed (`~`) between two
+  /// YAML documents, one per line. Nested keys are joined with dots. With `mask`,
+  /// only the keys are shown, so the diff can be shared without exposing any
+  /// secrets.
+  ///
+  /// # Arguments
+  /// * `old` - Old YAML document
+  /// * `new` - New YAML document
+  /// * `mask` - Leave out the values
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::PlaintextDiff;
+  ///
+  /// let old = "aws:\n  key: 1\n  secret: 2\nstripe: 3\n";
+  /// let new = "aws:\n  key: 1\n  secret: 20\ngithub: 4\n";
+  ///
+  /// assert_eq!(
+  ///   "~ aws.secret: 2 -> 20\n- stripe: 3\n+ github: 4\n",
+  ///   PlaintextDiff::yaml(old, new, false).unwrap()
+  /// );
+  /// assert_eq!(
+  ///   "~ aws.secret\n- stripe\n+ github\n",
+  ///   PlaintextDiff::yaml(old, new, true).unwrap()
+  /// );
+  /// ```
+  pub fn yaml(old: &str, new: &str, mask: bool) -> anyhow::Result<String> {
+    let old_values = Self::flatten_document(old)?;
+    let new_values = Self::flatten_document(new)?;
+    let old_lookup: HashMap<&str, &Value> = old_values
+      .iter()
+      .map(|(key, value)| (key.as_str(), value))
+      .collect();
+    let new_lookup: HashMap<&str, &Value> = new_values
+      .iter()
+      .map(|(key, value)| (key.as_str(), value))
+      .collect();
+    let mut diff = String::new();
+
+    for (key, old_value) in &old_values {
+      match new_lookup.get(key.as_str()) {
+        Some(new_value) if *new_value == old_value => {}
+        Some(_) if mask => diff.push_str(&format!("~ {}\n", key)),
+        Some(new_value) => diff.push_str(&format!(
+          "~ {}: {} -> {}\n",
+          key,
+          Self::display_value(old_value),
+          Self::display_value(new_value)
+        )),
+        None if mask => diff.push_str(&format!("- {}\n", key)),
+        None => diff.push_str(&format!("- {}: {}\n", key, Self::display_value(old_value))),
+      }
+    }
+
+    for (key, new_value) in &new_values {
+      if old_lookup.contains_key(key.as_str()) {
+        continue;
+      }
+
+      match mask {
+        true => diff.push_str(&format!("+ {}\n", key)),
+        false => diff.push_str(&format!("+ {}: {}\n", key, Self::display_value(new_value))),
+      }
+    }
+
+    Ok(diff)
+  }
+
+  /// Returns the leaf values of a YAML mapping in document order, keyed by
+  /// their dotted path.
+  fn flatten_document(contents: &str) -> anyhow::Result<Vec<(String, Value)>> {
+    let mut values = Vec::new();
+
+    match serde_yaml::from_str::<Value>(contents) {
+      Ok(Value::Mapping(mapping)) => Self::flatten(&mut values, "", &Value::Mapping(mapping)),
+      Ok(Value::Null) => {}
+      _ => return Err(anyhow!("Contents are not a YAML mapping")),
+    }
+
+    Ok(values)
+  }
+
+  fn flatten(values: &mut Vec<(String, Value)>, prefix: &str, value: &Value) {
+    match value {
+      Value::Mapping(mapping) if !mapping.is_empty() => {
+        for (key, value) in mapping {
+          let key = match prefix {
+            "" => Self::display_value(key),
+            _ => format!("{}.{}", prefix, Self::display_value(key)),
+          };
+
+          Self::flatten(values, &key, value);
+        }
+      }
+      _ => values.push((prefix.to_string(), value.clone())),
+    }
+  }
+
+  fn display_value(value: &Value) -> String {
+    match value {
+      Value::String(string) => string.clone(),
+      Value::Sequence(_) | Value::Mapping(_) | Value::Tagged(_) => {
+        serde_json::to_string(value).unwrap_or_default()
+      }
+      _ => serde_yaml::to_string(value)
+        .unwrap_or_default()
+        .trim()
+        .to_string(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_unified_without_changes() {
+    assert_eq!("", PlaintextDiff::unified("a", "same\n", "b", "same\n"));
+  }
+
+  #[test]
+  fn test_yaml_without_changes() -> anyhow::Result<()> {
+    assert_eq!("", PlaintextDiff::yaml("a: 1\n", "a: 1\n", false)?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_yaml_nested_and_empty_documents() -> anyhow::Result<()> {
+    assert_eq!(
+      "+ aws.keys: [1,2]\n+ empty: {}\n",
+      PlaintextDiff::yaml("", "aws:\n  keys: [1, 2]\nempty: {}\n", false)?
+    );
+    assert_eq!(
+      "- aws\n+ aws.key\n",
+      PlaintextDiff::yaml("aws: old\n", "aws:\n  key: new\n", true)?
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_yaml_mask_never_shows_values() -> anyhow::Result<()> {
+    let diff = PlaintextDiff::yaml("token: hunter2\n", "token: hunter3\nnew: swordfish\n", true)?;
+
+    assert_eq!("~ token\n+ new\n", diff);
+    assert!(!diff.contains("hunter"));
+    assert!(!diff.contains("swordfish"));
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_yaml_rejects_non_mapping_documents() {
+    assert!(PlaintextDiff::yaml("CHANGE ME", "a: 1\n", false).is_err());
+    assert!(PlaintextDiff::yaml("a: 1\n", "- 1\n- 2\n", false).is_err());
+  }
+}
@@ -1,7 +1,35 @@
+mod active_record_encryption;
+mod cipher;
 mod cipher_generation;
-mod file_encryptor;
+mod compression;
+mod create_options;
+mod digest;
+pub(crate) mod file_encryptor;
+mod file_header;
+mod key_format;
+mod key_generator;
+mod key_lookup;
+mod legacy_message_encryptor;
+mod message_adapters;
 mod message_encryptor;
+mod message_verifier;
+mod metadata;
+mod rails_cookie;
+mod stream_encryptor;
 
+pub use self::active_record_encryption::ActiveRecordEncryption;
+pub use self::cipher::Cipher;
 pub use self::cipher_generation::CipherGeneration;
+pub use self::compression::Compression;
+pub use self::create_options::{CreateOptions, CreatedFiles};
+pub use self::digest::Digest;
 pub use self::file_encryptor::FileEncryption;
+pub use self::key_format::KeyFormat;
+pub use self::key_generator::KeyGenerator;
+pub use self::key_lookup::{KeyLookup, KeySource};
+pub use self::legacy_message_encryptor::LegacyMessageEncryption;
+pub use self::message_adapters::{DecryptingReader, EncryptingWriter};
 pub use self::message_encryptor::MessageEncryption;
+pub use self::message_verifier::MessageVerifier;
+pub use self::rails_cookie::{CookiePayload, RailsCookie};
+pub use self::stream_encryptor::{StreamDecryptor, StreamEncryptor};
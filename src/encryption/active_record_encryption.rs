@@ -0,0 +1,362 @@
+use crate::encryption::{Compression, Digest};
+use crate::{CipherGeneration, KeyGenerator, MessageEncryption};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+static EMPTY_AAD_STRING: &str = "";
+/// Size in bytes above which Rails compresses values before encrypting them.
+static COMPRESSION_THRESHOLD: usize = 140;
+
+/// An encrypted column value, e.g. `{"p": ..., "h": {"iv": ..., "at": ...}}`.
+#[derive(Serialize, Deserialize)]
+struct EncryptedAttribute {
+  p: String,
+  h: Headers,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Headers {
+  iv: String,
+  at: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  e: Option<String>,
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  c: bool,
+}
+
+/// Reads and writes column values encrypted by ActiveRecord Encryption
+/// (`encrypts :email` in Rails 7+), so the same tables can be shared between
+/// Rails and Rust. Values are encrypted with AES-256-GCM using a key derived
+/// from `active_record_encryption.primary_key`, or from
+/// `active_record_encryption.deterministic_key` in deterministic mode.
+///
+/// # Examples
+///
+/// ```
+/// use street_cred::ActiveRecordEncryption;
+///
+/// let encryption = ActiveRecordEncryption::new("primary-key", "derivation-salt");
+/// let column_value = r#"{"p":"T9mmJgOefpojAKLmUXopG/c=","h":{"iv":"AAECAwQFBgcICQoL","at":"QM0CsqJZMCZZbCnVS8u3SA=="}}"#;
+///
+/// assert_eq!("jorge@example.com", encryption.decrypt(column_value).unwrap());
+/// ```
+pub struct ActiveRecordEncryption {
+  primary_key: String,
+  deterministic_key: Option<String>,
+  key_derivation_salt: String,
+  digest: Digest,
+  compression: Compression,
+  derived_key: OnceLock<String>,
+}
+
+impl ActiveRecordEncryption {
+  /// Create a new instance of ActiveRecordEncryption. The key derivation digest
+  /// defaults to SHA1, matching `active_record_encryption.hash_digest_class` in
+  /// Rails before 7.1.
+  ///
+  /// # Arguments
+  /// * `primary_key` - Value of `active_record_encryption.primary_key`
+  /// * `key_derivation_salt` - Value of `active_record_encryption.key_derivation_salt`
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::ActiveRecordEncryption;
+  ///
+  /// let encryption = ActiveRecordEncryption::new("primary-key", "derivation-salt");
+  /// ```
+  pub fn new(primary_key: &str, key_derivation_salt: &str) -> Self {
+    ActiveRecordEncryption {
+      primary_key: primary_key.to_string(),
+      deterministic_key: None,
+      key_derivation_salt: key_derivation_salt.to_string(),
+      digest: Digest::default(),
+      compression: Compression::Zlib,
+      derived_key: OnceLock::new(),
+    }
+  }
+
+  /// Switch to deterministic mode, used for attributes declared with
+  /// `encrypts :email, deterministic: true`. The same value always encrypts to
+  /// the same column value, which allows querying by it, at the cost of revealing
+  /// which rows hold equal values.
+  ///
+  /// # Arguments
+  /// * `deterministic_key` - Value of `active_record_encryption.deterministic_key`
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::ActiveRecordEncryption;
+  ///
+  /// let encryption = ActiveRecordEncryption::new("primary-key", "derivation-salt")
+  ///   .deterministic("deterministic-key");
+  /// ```
+  pub fn deterministic(mut self, deterministic_key: &str) -> Self {
+    self.deterministic_key = Some(deterministic_key.to_string());
+    self.derived_key = OnceLock::new();
+
+    self
+  }
+
+  /// Set the digest used to derive keys, i.e.
+  /// `active_record_encryption.hash_digest_class`. Applications on Rails 7.1
+  /// defaults use SHA256.
+  ///
+  /// # Arguments
+  /// * `digest` - Digest used for key derivation
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::{ActiveRecordEncryption, Digest};
+  ///
+  /// let encryption =
+  ///   ActiveRecordEncryption::new("primary-key", "derivation-salt").with_digest(Digest::Sha256);
+  /// ```
+  pub fn with_digest(mut self, digest: Digest) -> Self {
+    self.digest = digest;
+    self.derived_key = OnceLock::new();
+
+    self
+  }
+
+  /// Set the compression applied to values larger than 140 bytes, i.e.
+  /// `active_record_encryption.compressor`. Defaults to zlib, like Rails.
+  /// `Compression::None` disables compression, matching `compress: false`.
+  ///
+  /// # Arguments
+  /// * `compression` - Compression to apply before encrypting
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::{ActiveRecordEncryption, Compression};
+  ///
+  /// let encryption =
+  ///   ActiveRecordEncryption::new("primary-key", "derivation-salt").with_compression(Compression::None);
+  /// ```
+  pub fn with_compression(mut self, compression: Compression) -> Self {
+    self.compression = compression;
+
+    self
+  }
+
+  /// Decrypts an encrypted column value and returns the clear text.
+  ///
+  /// # Arguments
+  /// * `value` - Encrypted column value as stored in the database
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::ActiveRecordEncryption;
+  ///
+  /// let encryption = ActiveRecordEncryption::new("primary-key", "derivation-salt");
+  ///
+  /// assert!(encryption.decrypt("jorge@example.com").is_err());
+  /// ```
+  pub fn decrypt(&self, value: &str) -> anyhow::Result<String> {
+    let attribute: EncryptedAttribute =
+      serde_json::from_str(value).map_err(|_| anyhow!("Invalid encrypted attribute"))?;
+
+    let decryptor = MessageEncryption::new(attribute.p.into_bytes(), self.key(), EMPTY_AAD_STRING);
+    let mut decrypted = decryptor.decrypt_raw(&attribute.h.iv, &attribute.h.at)?;
+
+    if attribute.h.c {
+      decrypted = match Compression::detect(&decrypted) {
+        Compression::None => return Err(anyhow!("Invalid compressed attribute")),
+        _ => Compression::decompress(decrypted)?,
+      };
+    }
+
+    Ok(String::from_utf8(decrypted)?)
+  }
+
+  /// Encrypts `clear_text` and returns the column value to store in the database.
+  ///
+  /// # Arguments
+  /// * `clear_text` - Value to encrypt
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::ActiveRecordEncryption;
+  ///
+  /// let encryption = ActiveRecordEncryption::new("primary-key", "derivation-salt");
+  /// let column_value = encryption.encrypt("jorge@example.com").unwrap();
+  ///
+  /// assert_eq!("jorge@example.com", encryption.decrypt(&column_value).unwrap());
+  /// ```
+  pub fn encrypt(&self, clear_text: &str) -> anyhow::Result<String> {
+    let key = self.key();
+
+    // Like Rails, values above the threshold are always compressed, and the
+    // deterministic IV is derived from the payload that actually gets encrypted.
+    let compressed =
+      self.compression != Compression::None && clear_text.len() > COMPRESSION_THRESHOLD;
+    let plaintext = match compressed {
+      true => self.compression.compress(clear_text.as_bytes())?,
+      false => clear_text.as_bytes().to_vec(),
+    };
+    let iv = match self.deterministic_key {
+      Some(_) => CipherGeneration::deterministic_iv(key, &plaintext)?,
+      None => CipherGeneration::random_iv(),
+    };
+
+    let encryptor = MessageEncryption::new(Vec::new(), key, EMPTY_AAD_STRING);
+    let encrypted = encryptor.encrypt_raw_with_iv(&plaintext, &iv)?;
+    let split_contents = MessageEncryption::split_encrypted_contents(&encrypted)?;
+
+    let attribute = EncryptedAttribute {
+      p: split_contents[0].to_string(),
+      h: Headers {
+        iv: split_contents[1].to_string(),
+        at: split_contents[2].to_string(),
+        e: None,
+        c: compressed,
+      },
+    };
+
+    Ok(serde_json::to_string(&attribute)?)
+  }
+
+  fn key(&self) -> &str {
+    self.derived_key.get_or_init(|| {
+      let password = self
+        .deterministic_key
+        .as_deref()
+        .unwrap_or(&self.primary_key);
+
+      KeyGenerator::new(password)
+        .with_digest(self.digest)
+        .generate_key(&self.key_derivation_salt, 32)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  static PRIMARY_KEY: &str = "primary-key";
+  static DETERMINISTIC_KEY: &str = "deterministic-key";
+  static SALT: &str = "derivation-salt";
+
+  #[test]
+  fn test_decrypt_rails_value() -> anyhow::Result<()> {
+    let encryption = ActiveRecordEncryption::new(PRIMARY_KEY, SALT);
+    let value = r#"{"p":"T9mmJgOefpojAKLmUXopG/c=","h":{"iv":"AAECAwQFBgcICQoL","at":"QM0CsqJZMCZZbCnVS8u3SA==","e":"VVRGLTg="}}"#;
+
+    assert_eq!("jorge@example.com", encryption.decrypt(value)?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_decrypt_rails_value_with_sha256() -> anyhow::Result<()> {
+    let encryption = ActiveRecordEncryption::new(PRIMARY_KEY, SALT).with_digest(Digest::Sha256);
+    let value = r#"{"p":"mlp8Dx6SSnpA9VpsPZQatGs=","h":{"iv":"AAECAwQFBgcICQoL","at":"Q2omZuR5pnCZp6+pH4SETw=="}}"#;
+
+    assert_eq!("jorge@example.com", encryption.decrypt(value)?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_deterministic_encrypt_matches_rails() -> anyhow::Result<()> {
+    let encryption =
+      ActiveRecordEncryption::new(PRIMARY_KEY, SALT).deterministic(DETERMINISTIC_KEY);
+
+    assert_eq!(
+      r#"{"p":"BeQq4P1oCFuJPnDeVG9M7BQ=","h":{"iv":"iDwvYdC6NgzhAoHb","at":"bntIaaKYhKGJeLJ/qN4bxQ=="}}"#,
+      encryption.encrypt("jorge@example.com")?
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_deterministic_compressed_value_matches_rails() -> anyhow::Result<()> {
+    let encryption =
+      ActiveRecordEncryption::new(PRIMARY_KEY, SALT).deterministic(DETERMINISTIC_KEY);
+    let clear_text = "Jörg Müller, 221B Baker Street, London NW1 6XE; ".repeat(4);
+    let value = r#"{"p":"72O/Yx7lDOEP+oS/RTh5ey51bMZdpV0lvvLga1FTxMthKaJyVUsguTMsrK7O/aUP0lsGBWKakxTDyC6w+Wc=","h":{"iv":"mRLC9EiFuRFS+BKs","at":"lsdkGrbanY0EUJCQOAkCPQ==","c":true}}"#;
+
+    assert_eq!(clear_text, encryption.decrypt(value)?);
+    assert_eq!(value, encryption.encrypt(&clear_text)?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_non_deterministic_encrypt_uses_random_iv() -> anyhow::Result<()> {
+    let encryption = ActiveRecordEncryption::new(PRIMARY_KEY, SALT);
+
+    let first = encryption.encrypt("jorge@example.com")?;
+    let second = encryption.encrypt("jorge@example.com")?;
+
+    assert_ne!(first, second);
+    assert_eq!("jorge@example.com", encryption.decrypt(&first)?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_encrypt_compresses_large_values() -> anyhow::Result<()> {
+    let encryption = ActiveRecordEncryption::new(PRIMARY_KEY, SALT);
+    let clear_text = "jorge@example.com ".repeat(20);
+
+    let value = encryption.encrypt(&clear_text)?;
+    let attribute: EncryptedAttribute = serde_json::from_str(&value)?;
+
+    assert!(attribute.h.c);
+    assert_eq!(None, attribute.h.e);
+    assert_eq!(clear_text, encryption.decrypt(&value)?);
+
+    let uncompressed = ActiveRecordEncryption::new(PRIMARY_KEY, SALT)
+      .with_compression(Compression::None)
+      .encrypt(&clear_text)?;
+
+    assert!(!uncompressed.contains(r#""c":true"#));
+    assert_eq!(clear_text, encryption.decrypt(&uncompressed)?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_encrypt_does_not_compress_small_values() -> anyhow::Result<()> {
+    let encryption = ActiveRecordEncryption::new(PRIMARY_KEY, SALT);
+
+    let value = encryption.encrypt("jorge@example.com")?;
+
+    assert!(!value.contains(r#""c":true"#));
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_encrypt_empty_value() -> anyhow::Result<()> {
+    let encryption = ActiveRecordEncryption::new(PRIMARY_KEY, SALT);
+
+    let value = encryption.encrypt("")?;
+
+    assert_eq!("", encryption.decrypt(&value)?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_decrypt_fails_with_wrong_key() {
+    let encryption = ActiveRecordEncryption::new("another-key", SALT);
+    let value = r#"{"p":"T9mmJgOefpojAKLmUXopG/c=","h":{"iv":"AAECAwQFBgcICQoL","at":"QM0CsqJZMCZZbCnVS8u3SA=="}}"#;
+
+    assert!(encryption.decrypt(value).is_err());
+  }
+
+  #[test]
+  fn test_decrypt_fails_with_invalid_value() {
+    let encryption = ActiveRecordEncryption::new(PRIMARY_KEY, SALT);
+
+    assert!(
+      encryption
+        .decrypt(r#"{"p":"T9mmJgOefpojAKLmUXopG/c="}"#)
+        .is_err()
+    );
+  }
+}
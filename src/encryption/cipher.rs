@@ -0,0 +1,310 @@
+use aes_gcm::{
+  Aes128Gcm, Aes256Gcm,
+  aead::{Aead, KeyInit, Nonce, Payload},
+};
+use aes_gcm_siv::{Aes256GcmSiv, aead::Aead as _, aead::KeyInit as _};
+use anyhow::anyhow;
+use chacha20poly1305::XChaCha20Poly1305;
+use std::fmt;
+use std::str::FromStr;
+
+/// Authenticated cipher used to encrypt messages. AES-GCM is what Rails uses,
+/// and which of AES-128-GCM and AES-256-GCM applies is normally picked from the
+/// key length. XChaCha20-Poly1305 uses a 24 byte random nonce, so random nonces
+/// can be used for far more messages per key, and it is fast on machines
+/// without AES hardware acceleration. AES-256-GCM-SIV doesn't leak the key
+/// stream when a nonce is reused, so it suits files that are re-encrypted very
+/// often under the same key. Rails can't read either of them.
+///
+/// # Examples
+///
+/// ```
+/// use street_cred::Cipher;
+///
+/// let cipher: Cipher = "xchacha20-poly1305".parse().unwrap();
+///
+/// assert_eq!(Cipher::XChaCha20Poly1305, cipher);
+/// assert_eq!(24, cipher.nonce_len());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cipher {
+  /// AES-128-GCM, used by Rails for credentials files.
+  Aes128Gcm,
+  /// AES-256-GCM, used by Rails for messages and cookies.
+  Aes256Gcm,
+  /// XChaCha20-Poly1305
+  XChaCha20Poly1305,
+  /// AES-256-GCM-SIV (RFC 8452)
+  Aes256GcmSiv,
+}
+
+impl Cipher {
+  /// Size in bytes of the keys used by the cipher.
+  pub fn key_len(&self) -> usize {
+    match self {
+      Cipher::Aes128Gcm => 16,
+      Cipher::Aes256Gcm | Cipher::XChaCha20Poly1305 | Cipher::Aes256GcmSiv => 32,
+    }
+  }
+
+  /// Size in bytes of the nonces (initialization vectors) used by the cipher.
+  pub fn nonce_len(&self) -> usize {
+    match self {
+      Cipher::Aes128Gcm | Cipher::Aes256Gcm | Cipher::Aes256GcmSiv => 12,
+      Cipher::XChaCha20Poly1305 => 24,
+    }
+  }
+
+  /// Whether Rails can read messages encrypted with the cipher.
+  pub fn is_rails_compatible(&self) -> bool {
+    matches!(self, Cipher::Aes128Gcm | Cipher::Aes256Gcm)
+  }
+
+  /// Picks the AES-GCM variant matching the length of `key`, like Rails does.
+  ///
+  /// # Arguments
+  /// * `key` - Raw key bytes
+  ///
+  pub(crate) fn for_key(key: &[u8]) -> anyhow::Result<Cipher> {
+    match key.len() {
+      16 => Ok(Cipher::Aes128Gcm),
+      32 => Ok(Cipher::Aes256Gcm),
+      _ => Err(anyhow!("Invalid key length")),
+    }
+  }
+
+  /// Encrypts `plaintext` and returns the ciphertext with the authentication tag
+  /// appended.
+  ///
+  /// # Arguments
+  /// * `key` - Raw key bytes
+  /// * `nonce` - Nonce to encrypt with
+  /// * `plaintext` - Bytes to encrypt
+  /// * `aad` - Additional authenticated data
+  ///
+  pub(crate) fn encrypt(
+    &self,
+    key: &[u8],
+    nonce: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+  ) -> anyhow::Result<Vec<u8>> {
+    self.check_key(key)?;
+    self.check_nonce(nonce)?;
+
+    let payload = Payload {
+      msg: plaintext,
+      aad,
+    };
+
+    match self {
+      Cipher::Aes128Gcm => Aes128Gcm::new_from_slice(key)?
+        .encrypt(&Nonce::<Aes128Gcm>::try_from(nonce)?, payload)
+        .map_err(|_| anyhow!("Encryption not successful")),
+      Cipher::Aes256Gcm => Aes256Gcm::new_from_slice(key)?
+        .encrypt(&Nonce::<Aes256Gcm>::try_from(nonce)?, payload)
+        .map_err(|_| anyhow!("Encryption not successful")),
+      Cipher::XChaCha20Poly1305 => XChaCha20Poly1305::new_from_slice(key)?
+        .encrypt(&Nonce::<XChaCha20Poly1305>::try_from(nonce)?, payload)
+        .map_err(|_| anyhow!("Encryption not successful")),
+      Cipher::Aes256GcmSiv => Aes256GcmSiv::new_from_slice(key)
+        .map_err(|_| anyhow!("Invalid key length"))?
+        .encrypt(
+          nonce.into(),
+          aes_gcm_siv::aead::Payload {
+            msg: plaintext,
+            aad,
+          },
+        )
+        .map_err(|_| anyhow!("Encryption not successful")),
+    }
+  }
+
+  /// Decrypts `ciphertext`, which has the authentication tag appended.
+  ///
+  /// # Arguments
+  /// * `key` - Raw key bytes
+  /// * `nonce` - Nonce the ciphertext was encrypted with
+  /// * `ciphertext` - Bytes to decrypt
+  /// * `aad` - Additional authenticated data
+  ///
+  pub(crate) fn decrypt(
+    &self,
+    key: &[u8],
+    nonce: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+  ) -> anyhow::Result<Vec<u8>> {
+    self.check_key(key)?;
+    self.check_nonce(nonce)?;
+
+    let payload = Payload {
+      msg: ciphertext,
+      aad,
+    };
+
+    match self {
+      Cipher::Aes128Gcm => Aes128Gcm::new_from_slice(key)?
+        .decrypt(&Nonce::<Aes128Gcm>::try_from(nonce)?, payload)
+        .map_err(|_| anyhow!("Decryption not successful")),
+      Cipher::Aes256Gcm => Aes256Gcm::new_from_slice(key)?
+        .decrypt(&Nonce::<Aes256Gcm>::try_from(nonce)?, payload)
+        .map_err(|_| anyhow!("Decryption not successful")),
+      Cipher::XChaCha20Poly1305 => XChaCha20Poly1305::new_from_slice(key)?
+        .decrypt(&Nonce::<XChaCha20Poly1305>::try_from(nonce)?, payload)
+        .map_err(|_| anyhow!("Decryption not successful")),
+      Cipher::Aes256GcmSiv => Aes256GcmSiv::new_from_slice(key)
+        .map_err(|_| anyhow!("Invalid key length"))?
+        .decrypt(
+          nonce.into(),
+          aes_gcm_siv::aead::Payload {
+            msg: ciphertext,
+            aad,
+          },
+        )
+        .map_err(|_| anyhow!("Decryption not successful")),
+    }
+  }
+
+  fn check_key(&self, key: &[u8]) -> anyhow::Result<()> {
+    if key.len() == self.key_len() {
+      Ok(())
+    } else {
+      Err(anyhow!("Invalid key length"))
+    }
+  }
+
+  fn check_nonce(&self, nonce: &[u8]) -> anyhow::Result<()> {
+    if nonce.len() == self.nonce_len() {
+      Ok(())
+    } else {
+      Err(anyhow!("Invalid nonce length"))
+    }
+  }
+}
+
+impl fmt::Display for Cipher {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Cipher::Aes128Gcm => write!(f, "aes-128-gcm"),
+      Cipher::Aes256Gcm => write!(f, "aes-256-gcm"),
+      Cipher::XChaCha20Poly1305 => write!(f, "xchacha20-poly1305"),
+      Cipher::Aes256GcmSiv => write!(f, "aes-256-gcm-siv"),
+    }
+  }
+}
+
+impl FromStr for Cipher {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "aes-128-gcm" => Ok(Cipher::Aes128Gcm),
+      "aes-256-gcm" => Ok(Cipher::Aes256Gcm),
+      "xchacha20-poly1305" => Ok(Cipher::XChaCha20Poly1305),
+      "aes-256-gcm-siv" => Ok(Cipher::Aes256GcmSiv),
+      _ => Err(anyhow!("Unknown cipher: {}", s)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  static CIPHERS: [Cipher; 4] = [
+    Cipher::Aes128Gcm,
+    Cipher::Aes256Gcm,
+    Cipher::XChaCha20Poly1305,
+    Cipher::Aes256GcmSiv,
+  ];
+
+  #[test]
+  fn test_encrypt_decrypt_cycle() -> anyhow::Result<()> {
+    for cipher in CIPHERS {
+      let key = vec![7; cipher.key_len()];
+      let nonce = vec![1; cipher.nonce_len()];
+
+      let encrypted = cipher.encrypt(&key, &nonce, b"secret", b"aad")?;
+
+      assert_eq!(b"secret".len() + 16, encrypted.len());
+      assert_eq!(
+        b"secret",
+        cipher.decrypt(&key, &nonce, &encrypted, b"aad")?.as_slice()
+      );
+      assert!(cipher.decrypt(&key, &nonce, &encrypted, b"other").is_err());
+    }
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_xchacha20_poly1305_test_vector() -> anyhow::Result<()> {
+    // draft-irtf-cfrg-xchacha, appendix A.3.1
+    let key = hex::decode("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f")?;
+    let nonce = hex::decode("404142434445464748494a4b4c4d4e4f5051525354555657")?;
+    let aad = hex::decode("50515253c0c1c2c3c4c5c6c7")?;
+    let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+
+    let encrypted = Cipher::XChaCha20Poly1305.encrypt(&key, &nonce, plaintext, &aad)?;
+
+    assert_eq!(
+      "c0875924c1c7987947deafd8780acf49",
+      hex::encode(&encrypted[encrypted.len() - 16..])
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_aes_256_gcm_siv_test_vector() -> anyhow::Result<()> {
+    // RFC 8452, appendix C.2, first AEAD_AES_256_GCM_SIV vector with a plaintext
+    let mut key = vec![0; 32];
+    let mut nonce = vec![0; 12];
+
+    key[0] = 1;
+    nonce[0] = 3;
+
+    let encrypted =
+      Cipher::Aes256GcmSiv.encrypt(&key, &nonce, &hex::decode("0100000000000000")?, b"")?;
+
+    assert_eq!(
+      "c2ef328e5c71c83b843122130f7364b761e0b97427e3df28",
+      hex::encode(encrypted)
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_rejects_wrong_nonce_length() {
+    let key = vec![7; 32];
+
+    assert!(
+      Cipher::Aes256GcmSiv
+        .encrypt(&key, &[1; 24], b"secret", b"")
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn test_rejects_wrong_key_length() {
+    let nonce = vec![1; 24];
+
+    assert!(
+      Cipher::XChaCha20Poly1305
+        .encrypt(&[7; 16], &nonce, b"secret", b"")
+        .is_err()
+    );
+    assert!(Cipher::for_key(&[7; 24]).is_err());
+  }
+
+  #[test]
+  fn test_from_str_and_display() {
+    for cipher in CIPHERS {
+      assert_eq!(cipher, cipher.to_string().parse().unwrap());
+    }
+
+    assert!("aes-128-cbc".parse::<Cipher>().is_err());
+  }
+}
@@ -1,7 +1,9 @@
+use crate::{Cipher, Digest};
 use aes_gcm::{
   Aes128Gcm,
   aead::{Generate, Key},
 };
+use sha2::{Digest as _, Sha256};
 
 /// Collection of functions that generate random data for encryption/decryption.
 pub struct CipherGeneration {}
@@ -21,6 +23,61 @@ impl CipherGeneration {
     Self::random_bytes(12)
   }
 
+  /// Derives a 12 byte initialization vector from `data` and returns it as a
+  /// `Vec<u8>`. The IV is the first 12 bytes of an HMAC-SHA256 of `data` keyed
+  /// with `key`, which is how ActiveRecord Encryption derives IVs for
+  /// deterministic attributes.
+  ///
+  /// # Security
+  ///
+  /// Only use this when equal plaintexts *must* produce equal ciphertexts, e.g.
+  /// to look up encrypted values. Anyone who can see the ciphertexts learns which
+  /// of them hold the same plaintext. Prefer [CipherGeneration::random_iv]
+  /// everywhere else.
+  ///
+  /// # Arguments
+  /// * `key` - Hex encoded encryption key
+  /// * `data` - Plaintext that is about to be encrypted
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use street_cred::CipherGeneration;
+  ///
+  /// let key = "8872ebc11db3ea2ed08cc629d199b164";
+  /// let first_iv = CipherGeneration::deterministic_iv(key, b"jorge@example.com").unwrap();
+  /// let second_iv = CipherGeneration::deterministic_iv(key, b"jorge@example.com").unwrap();
+  ///
+  /// assert_eq!(first_iv, second_iv);
+  /// ```
+  pub fn deterministic_iv(key: &str, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let key = hex::decode(key)?;
+    let mut iv = Digest::Sha256.hmac(&key, data);
+
+    iv.truncate(12);
+
+    Ok(iv)
+  }
+
+  /// Generates a random nonce of the size `cipher` expects and returns it as a
+  /// `Vec<u8>`
+  ///
+  /// # Arguments
+  /// * `cipher` - Cipher the nonce will be used with
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use street_cred::{Cipher, CipherGeneration};
+  ///
+  /// let random_iv = CipherGeneration::random_iv_for(Cipher::XChaCha20Poly1305);
+  ///
+  /// assert_eq!(24, random_iv.len());
+  /// ```
+  pub fn random_iv_for(cipher: Cipher) -> Vec<u8> {
+    Self::random_bytes(cipher.nonce_len())
+  }
+
   /// Generates a random 16 byte encryption key and returns it as a
   /// `Vec<u8>`
   ///
@@ -37,13 +94,87 @@ impl CipherGeneration {
     hex::encode(key)
   }
 
+  /// Generates a random key of the size `cipher` expects and returns it hex
+  /// encoded.
+  ///
+  /// # Arguments
+  /// * `cipher` - Cipher the key will be used with
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use street_cred::{Cipher, CipherGeneration};
+  ///
+  /// let key = CipherGeneration::random_key_for(Cipher::XChaCha20Poly1305);
+  ///
+  /// assert_eq!(64, key.len());
+  /// ```
+  pub fn random_key_for(cipher: Cipher) -> String {
+    hex::encode(Self::random_bytes(cipher.key_len()))
+  }
+
+  /// Returns a short fingerprint identifying a key without revealing it: the
+  /// first 6 bytes of the SHA-256 of the key, hex encoded. Use it to tell which
+  /// key a file was encrypted with, or to compare keys across machines.
+  ///
+  /// # Arguments
+  /// * `key` - Hex encoded encryption key
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use street_cred::CipherGeneration;
+  ///
+  /// let fingerprint = CipherGeneration::key_fingerprint("200a0e90e538d17390c8c4bc3bc71e44").unwrap();
+  ///
+  /// assert_eq!("d5cb70d20a3e", fingerprint);
+  /// ```
+  pub fn key_fingerprint(key: &str) -> anyhow::Result<String> {
+    let key = hex::decode(key.trim())?;
+    let digest = Sha256::digest(&key);
+
+    Ok(hex::encode(&digest[..6]))
+  }
+
+  /// Derives a nonce of `length` bytes (at most 32) from `aad` and `data`.
+  /// Without additional authenticated data, this is the first `length` bytes of
+  /// an HMAC-SHA256 of `data`, exactly like [CipherGeneration::deterministic_iv]
+  /// and Rails. Otherwise the AAD is part of the HMAC input, prefixed with its
+  /// length, so the same plaintext encrypted with different AADs never reuses a
+  /// nonce. Rails never uses AAD for deterministic encryption, so those nonces
+  /// have no Rails equivalent.
+  ///
+  /// # Arguments
+  /// * `key` - Raw encryption key
+  /// * `aad` - Additional authenticated data the plaintext is encrypted with
+  /// * `data` - Plaintext that is about to be encrypted
+  /// * `length` - Size of the nonce to derive
+  ///
+  pub(crate) fn deterministic_nonce(key: &[u8], aad: &[u8], data: &[u8], length: usize) -> Vec<u8> {
+    let mut nonce = match aad.is_empty() {
+      true => Digest::Sha256.hmac(key, data),
+      false => {
+        let mut input = (aad.len() as u64).to_be_bytes().to_vec();
+
+        input.extend_from_slice(aad);
+        input.extend_from_slice(data);
+
+        Digest::Sha256.hmac(key, &input)
+      }
+    };
+
+    nonce.truncate(length);
+
+    nonce
+  }
+
   /// Generates a Vec of a specified length filled with random bytes and returns it as a
   /// `Vec<u8>`
   ///
   /// # Arguments
   /// * `length` - Size of the vector to generate
   ///
-  fn random_bytes(length: usize) -> Vec<u8> {
+  pub(crate) fn random_bytes(length: usize) -> Vec<u8> {
     let mut data = vec![0; length];
 
     getrandom::fill(&mut data).expect("failed to read random bytes from the operating system");
@@ -64,6 +195,60 @@ mod tests {
     assert_ne!(first_random_iv, second_random_iv);
   }
 
+  #[test]
+  fn test_deterministic_iv() -> anyhow::Result<()> {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+
+    let first_iv = CipherGeneration::deterministic_iv(key, b"first")?;
+    let second_iv = CipherGeneration::deterministic_iv(key, b"second")?;
+
+    assert_eq!(12, first_iv.len());
+    assert_eq!(first_iv, CipherGeneration::deterministic_iv(key, b"first")?);
+    assert_ne!(first_iv, second_iv);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_deterministic_nonce_depends_on_aad() {
+    let key = b"0123456789abcdef";
+    let nonce = CipherGeneration::deterministic_nonce(key, b"users", b"first", 12);
+
+    assert_eq!(
+      nonce,
+      CipherGeneration::deterministic_nonce(key, b"users", b"first", 12)
+    );
+    assert_ne!(
+      nonce,
+      CipherGeneration::deterministic_nonce(key, b"accounts", b"first", 12)
+    );
+    assert_ne!(
+      nonce,
+      CipherGeneration::deterministic_nonce(key, b"", b"first", 12)
+    );
+    assert_ne!(
+      CipherGeneration::deterministic_nonce(key, b"ab", b"c", 12),
+      CipherGeneration::deterministic_nonce(key, b"a", b"bc", 12)
+    );
+  }
+
+  #[test]
+  fn test_deterministic_nonce_without_aad_matches_deterministic_iv() -> anyhow::Result<()> {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+
+    assert_eq!(
+      CipherGeneration::deterministic_iv(key, b"first")?,
+      CipherGeneration::deterministic_nonce(&hex::decode(key)?, b"", b"first", 12)
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_deterministic_iv_with_invalid_key() {
+    assert!(CipherGeneration::deterministic_iv("not hex", b"first").is_err());
+  }
+
   #[test]
   fn test_random_key() {
     let first_random_key = CipherGeneration::random_key();
@@ -72,6 +257,42 @@ mod tests {
     assert_ne!(first_random_key, second_random_key);
   }
 
+  #[test]
+  fn test_random_iv_and_key_for_cipher() {
+    for cipher in [
+      Cipher::Aes128Gcm,
+      Cipher::Aes256Gcm,
+      Cipher::XChaCha20Poly1305,
+    ] {
+      assert_eq!(
+        cipher.nonce_len(),
+        CipherGeneration::random_iv_for(cipher).len()
+      );
+      assert_eq!(
+        cipher.key_len() * 2,
+        CipherGeneration::random_key_for(cipher).len()
+      );
+    }
+  }
+
+  #[test]
+  fn test_key_fingerprint() -> anyhow::Result<()> {
+    let fingerprint = CipherGeneration::key_fingerprint("200a0e90e538d17390c8c4bc3bc71e44")?;
+
+    assert_eq!(12, fingerprint.len());
+    assert_eq!(
+      fingerprint,
+      CipherGeneration::key_fingerprint("200A0E90E538D17390C8C4BC3BC71E44\n")?
+    );
+    assert_ne!(
+      fingerprint,
+      CipherGeneration::key_fingerprint("8872ebc11db3ea2ed08cc629d199b164")?
+    );
+    assert!(CipherGeneration::key_fingerprint("not hex").is_err());
+
+    Ok(())
+  }
+
   #[test]
   fn test_random_bytes() {
     let first_random_bytes = CipherGeneration::random_bytes(10);
@@ -0,0 +1,201 @@
+use anyhow::anyhow;
+use flate2::Compression as ZlibLevel;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+static ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+
+/// Compression applied to a message before it is encrypted. Compressed
+/// messages are detected automatically when decrypting, so readers don't need
+/// to know which compression, if any, was used.
+///
+/// # Examples
+///
+/// ```
+/// use street_cred::Compression;
+///
+/// let compression: Compression = "zstd".parse().unwrap();
+///
+/// assert_eq!(Compression::Zstd, compression);
+/// assert_eq!(Compression::None, Compression::default());
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+  /// Messages are encrypted as is.
+  #[default]
+  None,
+  /// zlib (deflate), as used by ActiveRecord Encryption. This links the
+  /// reference zlib library, so compressed values are byte for byte what Ruby's
+  /// `Zlib::Deflate` produces and deterministic ciphertexts match Rails.
+  Zlib,
+  /// Zstandard
+  Zstd,
+}
+
+impl Compression {
+  /// Compresses `data` and returns the compressed bytes.
+  ///
+  /// # Arguments
+  /// * `data` - Bytes to compress
+  ///
+  pub(crate) fn compress(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    match self {
+      Compression::None => Ok(data.to_vec()),
+      Compression::Zlib => {
+        let mut encoder = ZlibEncoder::new(Vec::new(), ZlibLevel::default());
+
+        encoder.write_all(data)?;
+
+        Ok(encoder.finish()?)
+      }
+      Compression::Zstd => Ok(zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+    }
+  }
+
+  /// Compresses `data` when it is larger than `threshold` bytes and compressing
+  /// actually makes it smaller. Returns `None` when `data` should be stored as is.
+  ///
+  /// # Arguments
+  /// * `data` - Bytes to compress
+  /// * `threshold` - Size in bytes above which compression is attempted
+  ///
+  pub(crate) fn compress_if_worth_it(
+    &self,
+    data: &[u8],
+    threshold: usize,
+  ) -> anyhow::Result<Option<Vec<u8>>> {
+    if *self == Compression::None || data.len() <= threshold {
+      return Ok(None);
+    }
+
+    let compressed = self.compress(data)?;
+
+    if compressed.len() < data.len() {
+      Ok(Some(compressed))
+    } else {
+      Ok(None)
+    }
+  }
+
+  /// Detects which compression was applied to `data` by looking at its header.
+  ///
+  /// # Arguments
+  /// * `data` - Bytes that may be compressed
+  ///
+  pub(crate) fn detect(data: &[u8]) -> Compression {
+    match data {
+      [cmf, flg, ..] if *cmf == 0x78 && u16::from_be_bytes([*cmf, *flg]) % 31 == 0 => {
+        Compression::Zlib
+      }
+      _ if data.starts_with(ZSTD_MAGIC) => Compression::Zstd,
+      _ => Compression::None,
+    }
+  }
+
+  /// Decompresses `data` with whichever compression [Compression::detect] finds,
+  /// returning it as is when it isn't compressed.
+  ///
+  /// # Arguments
+  /// * `data` - Bytes that may be compressed
+  ///
+  pub(crate) fn decompress(data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    match Self::detect(&data) {
+      Compression::None => Ok(data),
+      Compression::Zlib => {
+        let mut decompressed = Vec::new();
+
+        ZlibDecoder::new(data.as_slice()).read_to_end(&mut decompressed)?;
+
+        Ok(decompressed)
+      }
+      Compression::Zstd => Ok(zstd::decode_all(data.as_slice())?),
+    }
+  }
+}
+
+impl fmt::Display for Compression {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Compression::None => write!(f, "none"),
+      Compression::Zlib => write!(f, "zlib"),
+      Compression::Zstd => write!(f, "zstd"),
+    }
+  }
+}
+
+impl FromStr for Compression {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "none" => Ok(Compression::None),
+      "zlib" => Ok(Compression::Zlib),
+      "zstd" => Ok(Compression::Zstd),
+      _ => Err(anyhow!("Unknown compression: {}", s)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_compress_decompress_cycle() -> anyhow::Result<()> {
+    let data = "banana: true\n".repeat(100);
+
+    for compression in [Compression::None, Compression::Zlib, Compression::Zstd] {
+      let compressed = compression.compress(data.as_bytes())?;
+
+      assert_eq!(compression, Compression::detect(&compressed));
+      assert_eq!(data.as_bytes(), Compression::decompress(compressed)?);
+    }
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_compress_if_worth_it() -> anyhow::Result<()> {
+    let data = "banana: true\n".repeat(100);
+
+    assert!(
+      Compression::Zlib
+        .compress_if_worth_it(data.as_bytes(), 140)?
+        .is_some()
+    );
+    assert!(
+      Compression::Zlib
+        .compress_if_worth_it(data.as_bytes(), data.len())?
+        .is_none()
+    );
+    assert!(
+      Compression::None
+        .compress_if_worth_it(data.as_bytes(), 0)?
+        .is_none()
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_detect_leaves_serialized_data_alone() {
+    assert_eq!(
+      Compression::None,
+      Compression::detect(b"\x04\x08I\"\x1dPeanut Butter Jelly Time\x06:\x06ET")
+    );
+    assert_eq!(Compression::None, Compression::detect(b"{\"_rails\":{}}"));
+    assert_eq!(Compression::None, Compression::detect(b""));
+  }
+
+  #[test]
+  fn test_from_str_and_display() {
+    for compression in [Compression::None, Compression::Zlib, Compression::Zstd] {
+      assert_eq!(compression, compression.to_string().parse().unwrap());
+    }
+
+    assert!("gzip".parse::<Compression>().is_err());
+  }
+}
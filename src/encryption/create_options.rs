@@ -0,0 +1,232 @@
+use crate::CipherGeneration;
+use crate::encryption::Cipher;
+use std::path::PathBuf;
+
+/// Contents written to new credentials files unless a template is given.
+static PLACEHOLDER_TEMPLATE: &str = "CHANGE ME";
+/// Default name of new credentials files.
+static DEFAULT_FILE_NAME: &str = "credentials.yml.enc";
+/// Default name of new key files.
+static DEFAULT_KEY_FILE_NAME: &str = "master.key";
+
+#[derive(Clone, Debug)]
+enum Template {
+  Placeholder,
+  Rails,
+  Contents(String),
+}
+
+/// Options for initializing a new credentials file and master key with
+/// [crate::FileEncryption::create_with].
+///
+/// # Examples
+///
+/// ```
+/// use street_cred::{Cipher, CreateOptions};
+///
+/// let options = CreateOptions::new()
+///   .with_cipher(Cipher::XChaCha20Poly1305)
+///   .with_rails_template()
+///   .with_file_name("production.yml.enc")
+///   .without_gitignore();
+/// ```
+#[derive(Clone, Debug)]
+pub struct CreateOptions {
+  pub(crate) cipher: Cipher,
+  pub(crate) gitignore: bool,
+  pub(crate) file_name: String,
+  pub(crate) key_file_name: String,
+  template: Template,
+}
+
+impl CreateOptions {
+  /// Create the default options: AES-128-GCM, like Rails, a "CHANGE ME"
+  /// placeholder in `credentials.yml.enc` with the key in `master.key`, and adding
+  /// the master key and plaintext file to `.gitignore` when creating them in a git
+  /// repository.
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::CreateOptions;
+  ///
+  /// let options = CreateOptions::new();
+  /// ```
+  pub fn new() -> Self {
+    CreateOptions {
+      cipher: Cipher::Aes128Gcm,
+      gitignore: true,
+      file_name: DEFAULT_FILE_NAME.to_string(),
+      key_file_name: DEFAULT_KEY_FILE_NAME.to_string(),
+      template: Template::Placeholder,
+    }
+  }
+
+  /// Set the cipher to encrypt the credentials file with. The master key is
+  /// generated with the size the cipher expects.
+  ///
+  /// # Arguments
+  /// * `cipher` - Cipher to encrypt with
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::{Cipher, CreateOptions};
+  ///
+  /// let options = CreateOptions::new().with_cipher(Cipher::Aes256Gcm);
+  /// ```
+  pub fn with_cipher(mut self, cipher: Cipher) -> Self {
+    self.cipher = cipher;
+
+    self
+  }
+
+  /// Set the initial contents of the credentials file.
+  ///
+  /// # Arguments
+  /// * `contents` - Plaintext contents of the new credentials file
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::CreateOptions;
+  ///
+  /// let options = CreateOptions::new().with_template("api_key: CHANGE ME\n");
+  /// ```
+  pub fn with_template(mut self, contents: &str) -> Self {
+    self.template = Template::Contents(contents.to_string());
+
+    self
+  }
+
+  /// Start the credentials file from the template Rails uses, with a freshly
+  /// generated `secret_key_base` and commented examples.
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::CreateOptions;
+  ///
+  /// let options = CreateOptions::new().with_rails_template();
+  /// ```
+  pub fn with_rails_template(mut self) -> Self {
+    self.template = Template::Rails;
+
+    self
+  }
+
+  /// Set the name of the credentials file created when initializing a
+  /// directory.
+  ///
+  /// # Arguments
+  /// * `file_name` - Name of the encrypted credentials file
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::CreateOptions;
+  ///
+  /// let options = CreateOptions::new().with_file_name("secrets.txt.enc");
+  /// ```
+  pub fn with_file_name(mut self, file_name: &str) -> Self {
+    self.file_name = file_name.to_string();
+
+    self
+  }
+
+  /// Set the name of the key file, created next to the credentials file.
+  ///
+  /// # Arguments
+  /// * `key_file_name` - Name of the key file
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::CreateOptions;
+  ///
+  /// let options = CreateOptions::new().with_key_file_name("production.key");
+  /// ```
+  pub fn with_key_file_name(mut self, key_file_name: &str) -> Self {
+    self.key_file_name = key_file_name.to_string();
+
+    self
+  }
+
+  /// Leave `.gitignore` untouched.
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::CreateOptions;
+  ///
+  /// let options = CreateOptions::new().without_gitignore();
+  /// ```
+  pub fn without_gitignore(mut self) -> Self {
+    self.gitignore = false;
+
+    self
+  }
+
+  /// Returns the plaintext contents of a new credentials file.
+  pub(crate) fn template_contents(&self) -> String {
+    match &self.template {
+      Template::Placeholder => PLACEHOLDER_TEMPLATE.to_string(),
+      Template::Rails => Self::rails_template(),
+      Template::Contents(contents) => contents.clone(),
+    }
+  }
+
+  fn rails_template() -> String {
+    format!(
+      "# aws:\n\
+       #   access_key_id: 123\n\
+       #   secret_access_key: 345\n\
+       \n\
+       # Used as the base secret for all MessageVerifiers in Rails, including the one protecting cookies.\n\
+       secret_key_base: {}\n",
+      hex::encode(CipherGeneration::random_bytes(64))
+    )
+  }
+}
+
+impl Default for CreateOptions {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Files written by [crate::FileEncryption::create_with].
+#[derive(Clone, Debug, PartialEq)]
+pub struct CreatedFiles {
+  /// Path of the generated master key
+  pub key_path: PathBuf,
+  /// Path of the encrypted credentials file
+  pub encrypted_file_path: PathBuf,
+  /// Entries appended to `.gitignore`, empty when it was left untouched
+  pub ignored_entries: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_template_contents() {
+    assert_eq!("CHANGE ME", CreateOptions::new().template_contents());
+    assert_eq!(
+      "api_key: 123\n",
+      CreateOptions::new()
+        .with_template("api_key: 123\n")
+        .template_contents()
+    );
+  }
+
+  #[test]
+  fn test_rails_template_generates_secret_key_base() {
+    let options = CreateOptions::new().with_rails_template();
+    let first = options.template_contents();
+    let second = options.template_contents();
+
+    let secret_key_base = first
+      .lines()
+      .find_map(|line| line.strip_prefix("secret_key_base: "))
+      .unwrap();
+
+    assert_eq!(128, secret_key_base.len());
+    assert!(first.starts_with("# aws:\n#   access_key_id: 123\n"));
+    assert_ne!(first, second);
+  }
+}
@@ -0,0 +1,144 @@
+use hmac::{Hmac, KeyInit, Mac};
+
+/// Hash functions that can be used for message signing and key derivation.
+///
+/// # Examples
+///
+/// ```
+/// use street_cred::Digest;
+///
+/// let digest = Digest::Sha256;
+///
+/// assert_eq!(Digest::Sha1, Digest::default());
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Digest {
+  /// SHA1, the default used by older versions of Rails.
+  #[default]
+  Sha1,
+  /// SHA256
+  Sha256,
+}
+
+impl Digest {
+  /// Computes the HMAC of `data` using `key` and returns it as a `Vec<u8>`
+  ///
+  /// # Arguments
+  /// * `key` - Secret used to sign the data
+  /// * `data` - Data to sign
+  ///
+  pub(crate) fn hmac(&self, key: &[u8], data: &[u8]) -> Vec<u8> {
+    match self {
+      Digest::Sha1 => {
+        let mut mac =
+          Hmac::<sha1::Sha1>::new_from_slice(key).expect("HMAC accepts keys of any size");
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+      }
+      Digest::Sha256 => {
+        let mut mac =
+          Hmac::<sha2::Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+      }
+    }
+  }
+
+  /// Checks in constant time that `hex_digest` is the HMAC of `data` using `key`.
+  ///
+  /// # Arguments
+  /// * `key` - Secret used to sign the data
+  /// * `data` - Data that was signed
+  /// * `hex_digest` - Hex encoded signature to check
+  ///
+  pub(crate) fn verify_hex(&self, key: &[u8], data: &[u8], hex_digest: &str) -> bool {
+    let Ok(expected) = hex::decode(hex_digest) else {
+      return false;
+    };
+
+    match self {
+      Digest::Sha1 => {
+        let mut mac =
+          Hmac::<sha1::Sha1>::new_from_slice(key).expect("HMAC accepts keys of any size");
+        mac.update(data);
+        mac.verify_slice(&expected).is_ok()
+      }
+      Digest::Sha256 => {
+        let mut mac =
+          Hmac::<sha2::Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+        mac.update(data);
+        mac.verify_slice(&expected).is_ok()
+      }
+    }
+  }
+
+  /// Derives a key from `password` using PBKDF2 with this digest as the
+  /// underlying HMAC, filling `output` with the derived bytes.
+  ///
+  /// # Arguments
+  /// * `password` - Secret to derive the key from
+  /// * `salt` - Salt to use for the derivation
+  /// * `iterations` - Number of PBKDF2 rounds
+  /// * `output` - Buffer to fill with the derived key
+  ///
+  pub(crate) fn pbkdf2(&self, password: &[u8], salt: &[u8], iterations: u32, output: &mut [u8]) {
+    match self {
+      Digest::Sha1 => pbkdf2::pbkdf2_hmac::<sha1::Sha1>(password, salt, iterations, output),
+      Digest::Sha256 => pbkdf2::pbkdf2_hmac::<sha2::Sha256>(password, salt, iterations, output),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_hmac_sha1() {
+    let result = Digest::Sha1.hmac(b"key", b"The quick brown fox jumps over the lazy dog");
+
+    assert_eq!(
+      "de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9",
+      hex::encode(result)
+    );
+  }
+
+  #[test]
+  fn test_hmac_sha256() {
+    let result = Digest::Sha256.hmac(b"key", b"The quick brown fox jumps over the lazy dog");
+
+    assert_eq!(
+      "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8",
+      hex::encode(result)
+    );
+  }
+
+  #[test]
+  fn test_verify_hex() {
+    let data = b"The quick brown fox jumps over the lazy dog";
+    let signature = "de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9";
+
+    assert!(Digest::Sha1.verify_hex(b"key", data, signature));
+    assert!(!Digest::Sha256.verify_hex(b"key", data, signature));
+    assert!(!Digest::Sha1.verify_hex(b"another key", data, signature));
+    assert!(!Digest::Sha1.verify_hex(b"key", data, "not hex"));
+  }
+
+  #[test]
+  fn test_pbkdf2() {
+    let mut sha1_output = [0; 20];
+    let mut sha256_output = [0; 32];
+
+    Digest::Sha1.pbkdf2(b"password", b"salt", 1, &mut sha1_output);
+    Digest::Sha256.pbkdf2(b"password", b"salt", 1, &mut sha256_output);
+
+    assert_eq!(
+      "0c60c80f961f0e71f3a9b524af6012062fe037a6",
+      hex::encode(sha1_output)
+    );
+    assert_eq!(
+      "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b",
+      hex::encode(sha256_output)
+    );
+  }
+}
@@ -1,14 +1,15 @@
 use crate::CipherGeneration;
 use crate::MessageEncryption;
+use crate::encryption::file_header::FileHeader;
+use crate::encryption::{Cipher, Compression, CreateOptions, CreatedFiles};
 use anyhow::{Context, anyhow};
 use std::env;
 use std::ffi::OsStr;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process;
 use std::{fs, io};
 
-static EMPTY_AAD_STRING: &str = "";
-
 /// Represents an encryped file that we can edit the contents of while
 /// preserving the encryption.
 ///
@@ -34,7 +35,12 @@ static EMPTY_AAD_STRING: &str = "";
 /// ```
 pub struct FileEncryption {
   file_path: String,
-  key: String,
+  keys: Vec<String>,
+  compression: Compression,
+  compression_threshold: usize,
+  cipher: Option<Cipher>,
+  record_key_id: bool,
+  deterministic: bool,
 }
 
 impl FileEncryption {
@@ -56,10 +62,126 @@ impl FileEncryption {
   pub fn new(file_path: String, key: String) -> Self {
     FileEncryption {
       file_path: shellexpand::tilde(&file_path).to_string(),
-      key,
+      keys: vec![key],
+      compression: Compression::None,
+      compression_threshold: 0,
+      cipher: None,
+      record_key_id: false,
+      deterministic: false,
     }
   }
 
+  /// Add a fallback key to try when decrypting. The file is always encrypted
+  /// with the key given to [FileEncryption::new], so editing a file that was
+  /// encrypted with a fallback key re-encrypts it with the new key.
+  ///
+  /// # Arguments
+  /// * `key` - Key to fall back to when decrypting.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::FileEncryption;
+  ///
+  /// let file_path = String::from("some_file.txt");
+  /// let new_key = String::from("8872ebc11db3ea2ed08cc629d199b164");
+  /// let old_key = String::from("425D76994EE6101105DDDA2EE2604AA0");
+  /// let file_encryption = FileEncryption::new(file_path, new_key).rotate(old_key);
+  /// ```
+  pub fn rotate(mut self, key: String) -> Self {
+    self.keys.push(key);
+
+    self
+  }
+
+  /// Compress the contents larger than `threshold` bytes before encrypting them.
+  /// Compressed files are detected automatically when decrypting.
+  ///
+  /// # Arguments
+  /// * `compression` - Compression to apply
+  /// * `threshold` - Size in bytes above which contents are compressed
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::{Compression, FileEncryption};
+  ///
+  /// let file_path = String::from("some_file.txt");
+  /// let key = String::from("425D76994EE6101105DDDA2EE2604AA0");
+  /// let file_encryption = FileEncryption::new(file_path, key).with_compression(Compression::Zstd, 1024);
+  /// ```
+  pub fn with_compression(mut self, compression: Compression, threshold: usize) -> Self {
+    self.compression = compression;
+    self.compression_threshold = threshold;
+
+    self
+  }
+
+  /// Set the cipher used to encrypt the file. By default, files are encrypted
+  /// with the cipher recorded in them, or with AES-GCM (like Rails) when none is
+  /// recorded. Ciphers Rails can't read are recorded in a versioned header
+  /// ("SC2:<cipher>:<key id>:<serializer>:message--iv--tag"), so decrypting picks
+  /// them automatically. Files encrypted with AES-GCM keep the Rails-compatible
+  /// "message--iv--tag" format.
+  ///
+  /// # Arguments
+  /// * `cipher` - Cipher to encrypt with
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::{Cipher, FileEncryption};
+  ///
+  /// let file_path = String::from("some_file.txt");
+  /// let key = String::from("0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f9");
+  /// let file_encryption = FileEncryption::new(file_path, key).with_cipher(Cipher::XChaCha20Poly1305);
+  /// ```
+  pub fn with_cipher(mut self, cipher: Cipher) -> Self {
+    self.cipher = Some(cipher);
+
+    self
+  }
+
+  /// Record the fingerprint of the key (see [CipherGeneration::key_fingerprint])
+  /// in a versioned header, so decrypting with the wrong key reports which key
+  /// the file was encrypted with rather than a generic decryption error. Files
+  /// with a versioned header can't be read by Rails.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::FileEncryption;
+  ///
+  /// let file_path = String::from("some_file.txt");
+  /// let key = String::from("425D76994EE6101105DDDA2EE2604AA0");
+  /// let file_encryption = FileEncryption::new(file_path, key).record_key_id();
+  /// ```
+  pub fn record_key_id(mut self) -> Self {
+    self.record_key_id = true;
+
+    self
+  }
+
+  /// Encrypt deterministically, so the same contents encrypted with the same key
+  /// always produce the same file (see [MessageEncryption::deterministic]). This
+  /// keeps git from seeing changes when unchanged files are re-encrypted, at the
+  /// cost of revealing when two versions of a file are equal.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::FileEncryption;
+  ///
+  /// let file_path = String::from("some_file.txt");
+  /// let key = String::from("425D76994EE6101105DDDA2EE2604AA0");
+  /// let file_encryption = FileEncryption::new(file_path, key).deterministic();
+  /// ```
+  pub fn deterministic(mut self) -> Self {
+    self.deterministic = true;
+
+    self
+  }
+
   /// Initialize a new credentials file and master key in the current directory.
   ///
   /// # Example
@@ -74,33 +196,94 @@ impl FileEncryption {
   /// let _ = FileEncryption::create(&file_path);
   /// ```
   pub fn create(path: &str) -> anyhow::Result<()> {
-    let (filename, key_path, encrypted_file_path) = Self::output_info_for_create(path)?;
-
-    if !key_path.exists() && !encrypted_file_path.exists() {
-      let key = CipherGeneration::random_key();
+    Self::create_with_cipher(path, Cipher::Aes128Gcm)
+  }
 
-      fs::write(key_path, &key)?;
+  /// Initialize a new credentials file encrypted with `cipher`, and a master key
+  /// of the matching size, in the current directory.
+  ///
+  /// # Arguments
+  /// * `path` - Directory, or path of the credentials file, to create
+  /// * `cipher` - Cipher to encrypt the credentials file with
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use street_cred::{Cipher, FileEncryption};
+  /// # use assert_fs::prelude::*;
+  ///
+  /// # let file_path = assert_fs::TempDir::new().unwrap().to_string_lossy().to_string();
+  /// let _ = FileEncryption::create_with_cipher(&file_path, Cipher::XChaCha20Poly1305);
+  /// ```
+  pub fn create_with_cipher(path: &str, cipher: Cipher) -> anyhow::Result<()> {
+    Self::create_with(path, &CreateOptions::new().with_cipher(cipher))?;
 
-      let template_string = "CHANGE ME";
+    Ok(())
+  }
 
-      let fc = FileEncryption::new(filename, key);
-      let encrypted_contents = fc.encrypt(template_string.as_bytes())?;
+  /// Initialize a new credentials file and master key in the current directory.
+  /// The master key is only readable by its owner. When the directory is part of
+  /// a git repository, the master key and the plaintext name of the credentials
+  /// file are added to its `.gitignore`, unless disabled in `options`.
+  ///
+  /// # Arguments
+  /// * `path` - Directory, or path of the credentials file, to create
+  /// * `options` - Options for the new files
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use street_cred::{CreateOptions, FileEncryption};
+  /// # use assert_fs::prelude::*;
+  ///
+  /// # let temp_dir = assert_fs::TempDir::new().unwrap();
+  /// # let file_path = temp_dir.to_string_lossy().to_string();
+  /// let created_files = FileEncryption::create_with(&file_path, &CreateOptions::new()).unwrap();
+  ///
+  /// assert!(created_files.key_path.exists());
+  /// ```
+  pub fn create_with(path: &str, options: &CreateOptions) -> anyhow::Result<CreatedFiles> {
+    let (filename, key_path, encrypted_file_path) = Self::output_info_for_create(path, options)?;
 
-      fs::write(encrypted_file_path, encrypted_contents)?;
-    } else {
+    if key_path.exists() || encrypted_file_path.exists() {
       return Err(anyhow!(
-        "It seems you may have already initialized this directory. Either master.key and/or credentials.yml.enc already exist."
+        "It seems you may have already initialized this directory. Either {} and/or {} already exist.",
+        options.key_file_name,
+        filename
       ));
     }
 
-    Ok(())
+    let key = match options.cipher {
+      Cipher::Aes128Gcm => CipherGeneration::random_key(),
+      cipher => CipherGeneration::random_key_for(cipher),
+    };
+
+    Self::write_key_file(&key_path, &key)?;
+
+    let template_string = options.template_contents();
+
+    let fc = FileEncryption::new(filename.clone(), key).with_cipher(options.cipher);
+    let encrypted_contents = fc.encrypt(template_string.as_bytes())?;
+
+    fs::write(&encrypted_file_path, encrypted_contents)?;
+
+    let ignored_entries = match options.gitignore {
+      true => Self::update_gitignore(&key_path, &filename)?,
+      false => Vec::new(),
+    };
+
+    Ok(CreatedFiles {
+      key_path,
+      encrypted_file_path,
+      ignored_entries,
+    })
   }
 
   /// Edit the contents of an encrypted file via your preferred EDITOR.
   /// If no EDITOR environment variable is set, will default to vim.
   pub fn edit(&self) -> anyhow::Result<()> {
-    match self.decrypt() {
-      Ok(contents) => {
+    match self.decrypt_with_key_index() {
+      Ok((contents, key_index)) => {
         let temp_file_path = self.temp_file_location()?;
 
         self.write_file(temp_file_path.clone(), contents.clone())?;
@@ -110,8 +293,23 @@ impl FileEncryption {
         let old_file_contents = contents;
         let temp_file_contents = fs::read_to_string(temp_file_path.clone())?;
 
-        if old_file_contents != temp_file_contents {
-          let encrypted_contents = self.encrypt(temp_file_contents.as_bytes())?;
+        let (recorded_cipher, recorded_key_id) = self.recorded_format()?;
+        let cipher_changed = self.cipher.is_some_and(|cipher| match recorded_cipher {
+          Some(recorded_cipher) => cipher != recorded_cipher,
+          None => !cipher.is_rails_compatible(),
+        });
+        let key_id_added = self.record_key_id && recorded_key_id.is_none();
+
+        if old_file_contents != temp_file_contents
+          || key_index != 0
+          || cipher_changed
+          || key_id_added
+        {
+          let encrypted_contents = self.encrypt_with_format(
+            temp_file_contents.as_bytes(),
+            self.cipher.or(recorded_cipher),
+            self.record_key_id || recorded_key_id.is_some(),
+          )?;
 
           self.write_file(temp_file_path, encrypted_contents)?;
           self.replace_file_atomically()?;
@@ -142,17 +340,87 @@ impl FileEncryption {
   /// // let contents = file_encryption.decrypt()?;
   /// ```
   pub fn decrypt(&self) -> anyhow::Result<String> {
+    let (contents, _key_index) = self.decrypt_with_key_index()?;
+
+    Ok(contents)
+  }
+
+  /// Decrypts the contents of the `FileEncryption` and returns them along with
+  /// the index of the key that decrypted them. Index `0` is the key given to
+  /// [FileEncryption::new], followed by the keys added with
+  /// [FileEncryption::rotate] in order.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::FileEncryption;
+  ///
+  /// let file_path = String::from("some_file.txt");
+  /// let new_key = String::from("8872ebc11db3ea2ed08cc629d199b164");
+  /// let old_key = String::from("425D76994EE6101105DDDA2EE2604AA0");
+  /// let file_encryption = FileEncryption::new(file_path, new_key).rotate(old_key);
+  /// // let (contents, key_index) = file_encryption.decrypt_with_key_index()?;
+  /// ```
+  pub fn decrypt_with_key_index(&self) -> anyhow::Result<(String, usize)> {
     let contents = self.read_file()?;
-    let split_contents = MessageEncryption::split_encrypted_contents(&contents)?;
+
+    self.decrypt_contents_with_key_index(&contents)
+  }
+
+  /// Decrypts encrypted file contents that were read elsewhere, e.g. from stdin,
+  /// rather than from the file at the path of the `FileEncryption`.
+  ///
+  /// # Arguments
+  /// * `contents` - Encrypted contents, formatted like an encrypted file
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::FileEncryption;
+  ///
+  /// let file_path = String::from("some_file.txt");
+  /// let key = String::from("425D76994EE6101105DDDA2EE2604AA0");
+  /// let file_encryption = FileEncryption::new(file_path, key);
+  /// let encrypted_contents = file_encryption.encrypt(b"a secret message").unwrap();
+  ///
+  /// assert_eq!("a secret message", file_encryption.decrypt_contents(&encrypted_contents).unwrap());
+  /// ```
+  pub fn decrypt_contents(&self, contents: &str) -> anyhow::Result<String> {
+    let (contents, _key_index) = self.decrypt_contents_with_key_index(contents)?;
+
+    Ok(contents)
+  }
+
+  fn decrypt_contents_with_key_index(&self, contents: &str) -> anyhow::Result<(String, usize)> {
+    let (header, body) = FileHeader::parse(contents)?;
+    let split_contents = MessageEncryption::split_encrypted_contents(body)?;
     let message = split_contents[0];
     let iv = split_contents[1];
     let encrypted_aad = split_contents[2];
 
-    let decryptor =
-      MessageEncryption::new(message.as_bytes().to_vec(), &self.key, EMPTY_AAD_STRING);
+    let aad = header.as_ref().map(FileHeader::aad).unwrap_or_default();
+
+    let mut decryptor = self.keys.iter().skip(1).fold(
+      MessageEncryption::new(message.as_bytes().to_vec(), &self.keys[0], &aad),
+      |decryptor, key| decryptor.rotate(key),
+    );
+
+    if let Some(cipher) = Self::cipher_from(header.as_ref(), &split_contents)? {
+      decryptor = decryptor.with_cipher(cipher);
+    }
 
-    match decryptor.decrypt(iv, encrypted_aad) {
+    let key_id = header.and_then(|header| header.key_id);
+
+    if let Some(key_id) = &key_id {
+      self.check_key_id(key_id)?;
+    }
+
+    match decryptor.decrypt_with_key_index(iv, encrypted_aad) {
       Ok(decrypted_contents) => Ok(decrypted_contents),
+      Err(why) if key_id.is_some() => Err(anyhow!(
+        "Invalid encrypted contents in decrypt: {}. The key matches the one the file was encrypted with, so the file is corrupted or has been tampered with.",
+        why
+      )),
       Err(why) => Err(anyhow!("Invalid encrypted contents in decrypt: {}", why)),
     }
   }
@@ -172,11 +440,143 @@ impl FileEncryption {
   /// // let encrypted_contents = file_encryption.encrypt(contents)?;
   /// ```
   pub fn encrypt(&self, contents: &[u8]) -> anyhow::Result<String> {
-    let encryptor = MessageEncryption::new(contents.to_vec(), &self.key, EMPTY_AAD_STRING);
+    self.encrypt_with_format(contents, self.cipher, self.record_key_id)
+  }
+
+  /// Encrypts `contents` and replaces the encrypted file with them, keeping the
+  /// cipher and key ID it was encrypted with.
+  ///
+  /// # Arguments
+  /// * `contents` - New plaintext contents of the file
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::FileEncryption;
+  ///
+  /// let file_path = String::from("some_file.txt");
+  /// let key = String::from("425D76994EE6101105DDDA2EE2604AA0");
+  /// let file_encryption = FileEncryption::new(file_path, key);
+  ///
+  /// // file_encryption.write_contents(b"a secret message")?;
+  /// ```
+  pub fn write_contents(&self, contents: &[u8]) -> anyhow::Result<()> {
+    let (recorded_cipher, recorded_key_id) = self.recorded_format()?;
+    let encrypted_contents = self.encrypt_with_format(
+      contents,
+      self.cipher.or(recorded_cipher),
+      self.record_key_id || recorded_key_id.is_some(),
+    )?;
+
+    let path = PathBuf::from(&self.file_path);
+    let file_name = path
+      .file_name()
+      .context("Could not generate absolute path for encrypted file")?;
+    let temp_file_path = path.with_file_name(format!(
+      ".{}.{}.tmp",
+      file_name.to_string_lossy(),
+      process::id()
+    ));
+
+    self.write_file(&temp_file_path, encrypted_contents)?;
+    fs::rename(temp_file_path, path)?;
+
+    Ok(())
+  }
+
+  fn encrypt_with_format(
+    &self,
+    contents: &[u8],
+    cipher: Option<Cipher>,
+    record_key_id: bool,
+  ) -> anyhow::Result<String> {
+    // Ciphers Rails can't read and key IDs are recorded in a versioned header,
+    // which is authenticated along with the contents.
+    let header_cipher = match cipher {
+      Some(cipher) if !cipher.is_rails_compatible() => Some(cipher),
+      _ if record_key_id => match cipher {
+        Some(cipher) => Some(cipher),
+        None => Some(Cipher::for_key(&hex::decode(&self.keys[0])?)?),
+      },
+      _ => None,
+    };
+    let header = match header_cipher {
+      Some(header_cipher) if record_key_id => Some(FileHeader::new(
+        header_cipher,
+        Some(CipherGeneration::key_fingerprint(&self.keys[0])?),
+      )),
+      Some(header_cipher) => Some(FileHeader::new(header_cipher, None)),
+      None => None,
+    };
+    let aad = header.as_ref().map(FileHeader::aad).unwrap_or_default();
 
-    match encryptor.encrypt() {
-      Ok(encrypted_contents) => Ok(encrypted_contents),
-      Err(why) => Err(anyhow!("{}", why)),
+    let mut encryptor = MessageEncryption::new(contents.to_vec(), &self.keys[0], &aad)
+      .with_compression(self.compression, self.compression_threshold);
+
+    if let Some(cipher) = cipher {
+      encryptor = encryptor.with_cipher(cipher);
+    }
+
+    if self.deterministic {
+      encryptor = encryptor.deterministic();
+    }
+
+    let encrypted_contents = match encryptor.encrypt() {
+      Ok(encrypted_contents) => encrypted_contents,
+      Err(why) => return Err(anyhow!("{}", why)),
+    };
+
+    match header {
+      Some(header) => {
+        let split_contents = MessageEncryption::split_encrypted_contents(&encrypted_contents)?;
+
+        Ok(header.format(&split_contents[..3].join("--")))
+      }
+      None => Ok(encrypted_contents),
+    }
+  }
+
+  /// Returns the cipher and key ID recorded in the encrypted file, if any.
+  fn recorded_format(&self) -> anyhow::Result<(Option<Cipher>, Option<String>)> {
+    let contents = self.read_file()?;
+    let (header, body) = FileHeader::parse(&contents)?;
+    let split_contents = MessageEncryption::split_encrypted_contents(body)?;
+    let cipher = Self::cipher_from(header.as_ref(), &split_contents)?;
+
+    Ok((cipher, header.and_then(|header| header.key_id)))
+  }
+
+  /// Checks that one of the keys has the fingerprint recorded in the file.
+  fn check_key_id(&self, key_id: &str) -> anyhow::Result<()> {
+    let fingerprints = self
+      .keys
+      .iter()
+      .map(|key| CipherGeneration::key_fingerprint(key))
+      .collect::<anyhow::Result<Vec<String>>>()?;
+
+    if fingerprints.iter().any(|fingerprint| fingerprint == key_id) {
+      Ok(())
+    } else {
+      Err(anyhow!(
+        "Wrong key: this file was encrypted with key {}, but key {} was supplied",
+        key_id,
+        fingerprints.join(", ")
+      ))
+    }
+  }
+
+  /// Returns the cipher recorded in the header. Files only record their cipher
+  /// in the header, never as a fourth segment of the encrypted contents.
+  fn cipher_from(
+    header: Option<&FileHeader>,
+    split_contents: &[&str],
+  ) -> anyhow::Result<Option<Cipher>> {
+    match (header, split_contents.len()) {
+      (Some(header), 3) => Ok(Some(header.cipher)),
+      (None, 3) => Ok(None),
+      _ => Err(anyhow!(
+        "Invalid encrypted contents: files record their cipher in an SC2 header"
+      )),
     }
   }
 
@@ -252,7 +652,105 @@ impl FileEncryption {
     Ok(temp_directory_path)
   }
 
-  fn output_info_for_create(path: &str) -> anyhow::Result<(String, PathBuf, PathBuf)> {
+  /// Writes a new key file that only its owner can read and write. Fails when
+  /// the file already exists, so an existing key is never overwritten.
+  ///
+  /// # Arguments
+  /// * `key_path` - Path of the key file to create
+  /// * `key` - Hex encoded key to write
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use std::path::Path;
+  /// use street_cred::{CipherGeneration, FileEncryption};
+  ///
+  /// let key = CipherGeneration::random_key();
+  ///
+  /// FileEncryption::write_key_file(Path::new("production.key"), &key).unwrap();
+  /// ```
+  pub fn write_key_file(key_path: &Path, key: &str) -> anyhow::Result<()> {
+    let mut options = fs::OpenOptions::new();
+
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(key_path)?.write_all(key.as_bytes())?;
+
+    Ok(())
+  }
+
+  /// Appends the key file and the plaintext name of `filename` to the
+  /// `.gitignore` next to the key, when it is inside a git repository. Returns
+  /// the entries that were added.
+  fn update_gitignore(key_path: &Path, filename: &str) -> anyhow::Result<Vec<String>> {
+    let directory = fs::canonicalize(
+      key_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new(".")),
+    )?;
+
+    if !directory
+      .ancestors()
+      .any(|ancestor| ancestor.join(".git").exists())
+    {
+      return Ok(Vec::new());
+    }
+
+    let gitignore_path = directory.join(".gitignore");
+    let existing = match fs::read_to_string(&gitignore_path) {
+      Ok(existing) => existing,
+      Err(why) if why.kind() == io::ErrorKind::NotFound => String::new(),
+      Err(why) => return Err(why.into()),
+    };
+
+    let key_filename = key_path
+      .file_name()
+      .context("Could not get filename for key")?
+      .to_string_lossy()
+      .to_string();
+    let mut entries = vec![key_filename];
+
+    if let Some(plaintext_filename) = filename.strip_suffix(".enc") {
+      entries.push(plaintext_filename.to_string());
+    }
+
+    entries.retain(|entry| {
+      !existing
+        .lines()
+        .map(str::trim)
+        .any(|line| line == entry || line.strip_prefix('/') == Some(entry))
+    });
+
+    if !entries.is_empty() {
+      let mut contents = String::new();
+
+      if !existing.is_empty() && !existing.ends_with('\n') {
+        contents.push('\n');
+      }
+
+      for entry in &entries {
+        contents.push_str(entry);
+        contents.push('\n');
+      }
+
+      fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(gitignore_path)?
+        .write_all(contents.as_bytes())?;
+    }
+
+    Ok(entries)
+  }
+
+  fn output_info_for_create(
+    path: &str,
+    options: &CreateOptions,
+  ) -> anyhow::Result<(String, PathBuf, PathBuf)> {
     let mut pathbuf = PathBuf::from(path);
 
     let mut key_path;
@@ -260,9 +758,9 @@ impl FileEncryption {
 
     if pathbuf.is_dir() {
       encrypted_file_path = pathbuf.clone();
-      encrypted_file_path.push("credentials.yml.enc");
+      encrypted_file_path.push(&options.file_name);
 
-      pathbuf.push("master.key");
+      pathbuf.push(&options.key_file_name);
       key_path = pathbuf;
     } else {
       key_path = pathbuf
@@ -271,7 +769,7 @@ impl FileEncryption {
         .to_path_buf();
       encrypted_file_path = pathbuf;
 
-      key_path.push("master.key");
+      key_path.push(&options.key_file_name);
     }
 
     let filename = encrypted_file_path
@@ -285,7 +783,7 @@ impl FileEncryption {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
   use super::*;
   use assert_fs::prelude::*;
   use std::env::VarError;
@@ -446,6 +944,230 @@ mod tests {
     });
   }
 
+  #[test]
+  fn test_edit_with_rotated_key_reencrypts_with_new_key() {
+    with_env_vars(vec![("EDITOR", Some("echo"))], || {
+      let temp = assert_fs::TempDir::new().unwrap();
+      let input_file = temp.child("encoded.txt.enc");
+      temp
+        .copy_from("./tests/fixtures/", &["*.txt", "*.enc"])
+        .unwrap();
+
+      let old_key = String::from("200a0e90e538d17390c8c4bc3bc71e44");
+      let new_key = String::from("8872ebc11db3ea2ed08cc629d199b164");
+      let file_path = input_file.to_string_lossy().to_string();
+
+      let file_encryption = FileEncryption::new(file_path.clone(), new_key.clone()).rotate(old_key);
+      let (contents, key_index) = file_encryption.decrypt_with_key_index().unwrap();
+
+      assert_eq!(1, key_index);
+      assert!(file_encryption.edit().is_ok());
+
+      let file_encryption = FileEncryption::new(file_path, new_key);
+
+      assert_eq!(contents, file_encryption.decrypt().unwrap());
+    });
+  }
+
+  #[test]
+  fn test_encrypt_with_compression_decrypts_without_it() -> anyhow::Result<()> {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let input_file = temp.child("bundle.pem.enc");
+    let file_path = input_file.to_string_lossy().to_string();
+    let key = String::from("200a0e90e538d17390c8c4bc3bc71e44");
+    let contents = "-----BEGIN CERTIFICATE-----\n".repeat(100);
+
+    let file_encryption =
+      FileEncryption::new(file_path.clone(), key.clone()).with_compression(Compression::Zlib, 1024);
+    let encrypted = file_encryption.encrypt(contents.as_bytes())?;
+
+    input_file.write_str(&encrypted)?;
+
+    assert!(encrypted.len() < contents.len());
+    assert_eq!(contents, FileEncryption::new(file_path, key).decrypt()?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_edit_keeps_recorded_cipher() {
+    with_env_vars(vec![("EDITOR", Some("echo 'another' >> "))], || {
+      let temp = assert_fs::TempDir::new().unwrap();
+      let temp_path_string = temp.to_string_lossy().to_string();
+
+      FileEncryption::create_with_cipher(&temp_path_string, Cipher::XChaCha20Poly1305).unwrap();
+
+      let key = fs::read_to_string(temp.child("master.key")).unwrap();
+      let file_path = temp
+        .child("credentials.yml.enc")
+        .to_string_lossy()
+        .to_string();
+
+      assert_eq!(64, key.len());
+      assert!(
+        FileEncryption::new(file_path.clone(), key.clone())
+          .edit()
+          .is_ok()
+      );
+
+      let contents = fs::read_to_string(&file_path).unwrap();
+
+      assert!(contents.starts_with("SC2:xchacha20-poly1305::marshal:"));
+      assert_eq!(
+        "CHANGE MEanother\n",
+        FileEncryption::new(file_path, key).decrypt().unwrap()
+      );
+    });
+  }
+
+  #[test]
+  fn test_write_contents_keeps_recorded_format() -> anyhow::Result<()> {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let input_file = temp.child("secrets.txt.enc");
+    let file_path = input_file.to_string_lossy().to_string();
+    let key = CipherGeneration::random_key_for(Cipher::XChaCha20Poly1305);
+    let file_encryption = FileEncryption::new(file_path.clone(), key.clone())
+      .with_cipher(Cipher::XChaCha20Poly1305)
+      .record_key_id();
+
+    input_file.write_str(&file_encryption.encrypt(b"old")?)?;
+
+    FileEncryption::new(file_path.clone(), key.clone()).write_contents(b"new")?;
+
+    let contents = fs::read_to_string(&file_path)?;
+    let fingerprint = CipherGeneration::key_fingerprint(&key)?;
+
+    assert!(contents.starts_with(&format!("SC2:xchacha20-poly1305:{}:marshal:", fingerprint)));
+    assert_eq!("new", FileEncryption::new(file_path, key).decrypt()?);
+    assert_eq!(1, fs::read_dir(temp.path())?.count());
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_encrypt_writes_header_only_for_non_rails_ciphers() -> anyhow::Result<()> {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let input_file = temp.child("secrets.txt.enc");
+    let file_path = input_file.to_string_lossy().to_string();
+    let key = String::from("0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f9");
+
+    let rails_file = FileEncryption::new(file_path.clone(), key.clone());
+    let encrypted = rails_file.encrypt(b"secret")?;
+
+    assert_eq!(3, encrypted.split("--").count());
+
+    let file_encryption =
+      FileEncryption::new(file_path.clone(), key.clone()).with_cipher(Cipher::Aes256GcmSiv);
+    let encrypted = file_encryption.encrypt(b"secret")?;
+
+    assert!(encrypted.starts_with("SC2:aes-256-gcm-siv::marshal:"));
+
+    input_file.write_str(&encrypted)?;
+
+    assert_eq!("secret", FileEncryption::new(file_path, key).decrypt()?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_decrypt_rejects_four_part_contents() -> anyhow::Result<()> {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let input_file = temp.child("secrets.txt.enc");
+    let file_path = input_file.to_string_lossy().to_string();
+    let key = "0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f9";
+
+    let encryptor =
+      MessageEncryption::new(b"secret".to_vec(), key, "").with_cipher(Cipher::Aes256GcmSiv);
+
+    input_file.write_str(&encryptor.encrypt()?)?;
+
+    assert!(
+      FileEncryption::new(file_path, key.to_string())
+        .decrypt()
+        .is_err()
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_decrypt_rejects_changed_header() -> anyhow::Result<()> {
+    let key = "0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f9";
+    let file_encryption = FileEncryption::new("secrets.txt.enc".to_string(), key.to_string())
+      .with_cipher(Cipher::XChaCha20Poly1305);
+    let encrypted = file_encryption.encrypt(b"secret")?;
+
+    assert_eq!("secret", file_encryption.decrypt_contents(&encrypted)?);
+
+    let fingerprint = CipherGeneration::key_fingerprint(key)?;
+    let with_key_id = encrypted.replacen(
+      "SC2:xchacha20-poly1305::",
+      &format!("SC2:xchacha20-poly1305:{}:", fingerprint),
+      1,
+    );
+
+    assert_ne!(encrypted, with_key_id);
+    assert!(file_encryption.decrypt_contents(&with_key_id).is_err());
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_record_key_id_reports_wrong_key() -> anyhow::Result<()> {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let input_file = temp.child("secrets.txt.enc");
+    let file_path = input_file.to_string_lossy().to_string();
+    let key = String::from("200a0e90e538d17390c8c4bc3bc71e44");
+    let wrong_key = String::from("8872ebc11db3ea2ed08cc629d199b164");
+
+    let file_encryption = FileEncryption::new(file_path.clone(), key.clone()).record_key_id();
+    let encrypted = file_encryption.encrypt(b"secret")?;
+    let fingerprint = CipherGeneration::key_fingerprint(&key)?;
+
+    assert!(encrypted.starts_with(&format!("SC2:aes-128-gcm:{}:marshal:", fingerprint)));
+
+    input_file.write_str(&encrypted)?;
+
+    assert_eq!(
+      "secret",
+      FileEncryption::new(file_path.clone(), key).decrypt()?
+    );
+
+    let error = FileEncryption::new(file_path, wrong_key.clone())
+      .decrypt()
+      .unwrap_err()
+      .to_string();
+
+    assert!(error.contains(&fingerprint));
+    assert!(error.contains(&CipherGeneration::key_fingerprint(&wrong_key)?));
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_record_key_id_reports_tampering() -> anyhow::Result<()> {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let input_file = temp.child("secrets.txt.enc");
+    let file_path = input_file.to_string_lossy().to_string();
+    let key = String::from("200a0e90e538d17390c8c4bc3bc71e44");
+
+    let file_encryption = FileEncryption::new(file_path.clone(), key.clone()).record_key_id();
+    let encrypted = file_encryption.encrypt(b"secret")?;
+    let (header, body) = encrypted.rsplit_once(':').unwrap();
+    let tampered_body = match body.strip_prefix('A') {
+      Some(rest) => format!("B{}", rest),
+      None => format!("A{}", &body[1..]),
+    };
+
+    input_file.write_str(&format!("{}:{}", header, tampered_body))?;
+
+    let error = file_encryption.decrypt().unwrap_err().to_string();
+
+    assert!(error.contains("tampered"));
+
+    Ok(())
+  }
+
   #[test]
   fn test_create_with_dir() -> anyhow::Result<()> {
     let temp = assert_fs::TempDir::new().unwrap();
@@ -473,6 +1195,91 @@ mod tests {
     Ok(())
   }
 
+  #[test]
+  #[cfg(unix)]
+  fn test_create_key_is_only_readable_by_owner() -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = assert_fs::TempDir::new().unwrap();
+    let temp_path_string = temp.to_string_lossy().to_string();
+
+    FileEncryption::create(&temp_path_string)?;
+
+    let mode = fs::metadata(temp.child("master.key"))?.permissions().mode();
+
+    assert_eq!(0o600, mode & 0o777);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_create_updates_gitignore_in_git_repository() -> anyhow::Result<()> {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let temp_path_string = temp.to_string_lossy().to_string();
+
+    temp.child(".git").create_dir_all()?;
+    temp.child(".gitignore").write_str("/target\n/master.key")?;
+
+    let created_files = FileEncryption::create_with(&temp_path_string, &CreateOptions::new())?;
+
+    assert_eq!(vec!["credentials.yml"], created_files.ignored_entries);
+    assert_eq!(
+      "/target\n/master.key\ncredentials.yml\n",
+      fs::read_to_string(temp.child(".gitignore"))?
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_create_leaves_gitignore_alone() -> anyhow::Result<()> {
+    let git_repository = assert_fs::TempDir::new().unwrap();
+    let git_repository_path = git_repository.to_string_lossy().to_string();
+
+    git_repository.child(".git").create_dir_all()?;
+
+    let options = CreateOptions::new().without_gitignore();
+    let created_files = FileEncryption::create_with(&git_repository_path, &options)?;
+
+    assert!(created_files.ignored_entries.is_empty());
+    assert!(!git_repository.child(".gitignore").exists());
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_create_with_template_and_file_names() -> anyhow::Result<()> {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let temp_path_string = temp.to_string_lossy().to_string();
+    let options = CreateOptions::new()
+      .with_template("api_key: 123\n")
+      .with_file_name("production.yml.enc")
+      .with_key_file_name("production.key");
+
+    let created_files = FileEncryption::create_with(&temp_path_string, &options)?;
+
+    assert_eq!(temp.child("production.key").path(), created_files.key_path);
+    assert_eq!(
+      temp.child("production.yml.enc").path(),
+      created_files.encrypted_file_path
+    );
+
+    let key = fs::read_to_string(created_files.key_path)?;
+    let file_path = created_files.encrypted_file_path.to_string_lossy();
+
+    assert_eq!(
+      "api_key: 123\n",
+      FileEncryption::new(file_path.to_string(), key).decrypt()?
+    );
+    assert!(!temp.child("master.key").exists());
+
+    let second = FileEncryption::create_with(&temp_path_string, &options);
+
+    assert!(second.unwrap_err().to_string().contains("production.key"));
+
+    Ok(())
+  }
+
   #[test]
   fn test_create_with_invalid_path() -> anyhow::Result<()> {
     let result = FileEncryption::create("/not/real/path");
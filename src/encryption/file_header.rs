@@ -0,0 +1,153 @@
+use crate::encryption::Cipher;
+use anyhow::anyhow;
+
+/// Prefix of the only header version understood so far.
+static VERSION: &str = "SC2";
+/// Serializer used by [crate::FileEncryption] for file contents.
+static MARSHAL_SERIALIZER: &str = "marshal";
+
+/// Versioned header written in front of encrypted files that Rails can't read,
+/// recording how they were encrypted:
+/// "SC2:<cipher>:<key id>:<serializer>:message--iv--tag". The key ID is empty
+/// when it isn't recorded. The header is the additional authenticated data of
+/// the contents, so it can't be changed without decryption failing.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct FileHeader {
+  pub(crate) cipher: Cipher,
+  pub(crate) key_id: Option<String>,
+  pub(crate) serializer: String,
+}
+
+impl FileHeader {
+  /// Create a header for contents serialized with Ruby Marshal and encrypted
+  /// with `cipher`.
+  ///
+  /// # Arguments
+  /// * `cipher` - Cipher the contents are encrypted with
+  /// * `key_id` - Identifier of the key the contents are encrypted with
+  ///
+  pub(crate) fn new(cipher: Cipher, key_id: Option<String>) -> Self {
+    FileHeader {
+      cipher,
+      key_id,
+      serializer: MARSHAL_SERIALIZER.to_string(),
+    }
+  }
+
+  /// Splits encrypted file contents into their header, if they have one, and the
+  /// "message--iv--tag" part.
+  ///
+  /// # Arguments
+  /// * `contents` - The entire encrypted file
+  ///
+  pub(crate) fn parse(contents: &str) -> anyhow::Result<(Option<FileHeader>, &str)> {
+    let contents = contents.trim();
+
+    if !Self::is_versioned(contents) {
+      return Ok((None, contents));
+    }
+
+    let mut parts = contents.splitn(5, ':');
+    let version = parts.next().unwrap_or_default();
+
+    if version != VERSION {
+      return Err(anyhow!("Unsupported file format version: {}", version));
+    }
+
+    let (Some(cipher), Some(key_id), Some(serializer), Some(body)) =
+      (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+      return Err(anyhow!("Invalid file header"));
+    };
+
+    if serializer != MARSHAL_SERIALIZER {
+      return Err(anyhow!("Unsupported serializer: {}", serializer));
+    }
+
+    let header = FileHeader {
+      cipher: cipher.parse()?,
+      key_id: Some(key_id.to_string()).filter(|key_id| !key_id.is_empty()),
+      serializer: serializer.to_string(),
+    };
+
+    Ok((Some(header), body))
+  }
+
+  /// Prepends the header to "message--iv--tag".
+  ///
+  /// # Arguments
+  /// * `body` - Encrypted contents formatted like this: "message--iv--tag"
+  ///
+  pub(crate) fn format(&self, body: &str) -> String {
+    format!("{}{}", self.aad(), body)
+  }
+
+  /// Returns the header as written in front of the contents, which is the
+  /// additional authenticated data the contents are encrypted with.
+  pub(crate) fn aad(&self) -> String {
+    format!(
+      "{}:{}:{}:{}:",
+      VERSION,
+      self.cipher,
+      self.key_id.as_deref().unwrap_or_default(),
+      self.serializer
+    )
+  }
+
+  /// Base64 never contains ':', so anything shaped like "SC<digits>:" is a header.
+  fn is_versioned(contents: &str) -> bool {
+    match contents.split_once(':') {
+      Some((version, _)) => {
+        version.len() > 2
+          && version.starts_with("SC")
+          && version[2..].bytes().all(|byte| byte.is_ascii_digit())
+      }
+      None => false,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  static BODY: &str =
+    "HPxd1UcM3cH+Rt0HaIOFzdHqIPWIc3yR--/EoLW7ichWLzLh3V--7L1L8uPH7LoQYLkEfIckgA==";
+
+  #[test]
+  fn test_format_parse_cycle() -> anyhow::Result<()> {
+    for key_id in [None, Some("abc123".to_string())] {
+      let header = FileHeader::new(Cipher::XChaCha20Poly1305, key_id);
+      let contents = header.format(BODY);
+
+      assert_eq!((Some(header), BODY), FileHeader::parse(&contents)?);
+    }
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_format() {
+    let header = FileHeader::new(Cipher::Aes256GcmSiv, None);
+
+    assert_eq!(
+      format!("SC2:aes-256-gcm-siv::marshal:{}", BODY),
+      header.format(BODY)
+    );
+  }
+
+  #[test]
+  fn test_parse_without_header() -> anyhow::Result<()> {
+    assert_eq!((None, BODY), FileHeader::parse(&format!("{}\n", BODY))?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_parse_rejects_unknown_versions_and_fields() {
+    assert!(FileHeader::parse(&format!("SC3:aes-256-gcm-siv::marshal:{}", BODY)).is_err());
+    assert!(FileHeader::parse(&format!("SC2:aes-128-cbc::marshal:{}", BODY)).is_err());
+    assert!(FileHeader::parse(&format!("SC2:aes-256-gcm-siv::json:{}", BODY)).is_err());
+    assert!(FileHeader::parse("SC2:aes-256-gcm-siv").is_err());
+  }
+}
@@ -0,0 +1,128 @@
+use anyhow::anyhow;
+use base64::{Engine as _, engine::general_purpose};
+use std::fmt;
+use std::str::FromStr;
+
+/// Encoding of a key. street-cred and Rails store keys hex encoded, while other
+/// tools often expect base64 or the raw bytes.
+///
+/// # Examples
+///
+/// ```
+/// use street_cred::KeyFormat;
+///
+/// let key = KeyFormat::Hex.decode(b"200a0e90e538d17390c8c4bc3bc71e44").unwrap();
+///
+/// assert_eq!(b"IAoOkOU40XOQyMS8O8ceRA==".to_vec(), KeyFormat::Base64.encode(&key));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyFormat {
+  /// Hex encoded, as in `master.key`
+  #[default]
+  Hex,
+  /// Base64 encoded (standard alphabet, padded)
+  Base64,
+  /// Raw bytes
+  Raw,
+}
+
+impl KeyFormat {
+  /// Decodes a key in this format and returns its raw bytes. Surrounding
+  /// whitespace is ignored for the hex and base64 formats.
+  ///
+  /// # Arguments
+  /// * `key` - Encoded key
+  ///
+  pub fn decode(&self, key: &[u8]) -> anyhow::Result<Vec<u8>> {
+    match self {
+      KeyFormat::Hex => Ok(hex::decode(key.trim_ascii())?),
+      KeyFormat::Base64 => Ok(general_purpose::STANDARD.decode(key.trim_ascii())?),
+      KeyFormat::Raw => Ok(key.to_vec()),
+    }
+  }
+
+  /// Encodes the raw bytes of a key in this format.
+  ///
+  /// # Arguments
+  /// * `key` - Raw key bytes
+  ///
+  pub fn encode(&self, key: &[u8]) -> Vec<u8> {
+    match self {
+      KeyFormat::Hex => hex::encode(key).into_bytes(),
+      KeyFormat::Base64 => general_purpose::STANDARD.encode(key).into_bytes(),
+      KeyFormat::Raw => key.to_vec(),
+    }
+  }
+}
+
+impl fmt::Display for KeyFormat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      KeyFormat::Hex => write!(f, "hex"),
+      KeyFormat::Base64 => write!(f, "base64"),
+      KeyFormat::Raw => write!(f, "raw"),
+    }
+  }
+}
+
+impl FromStr for KeyFormat {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "hex" => Ok(KeyFormat::Hex),
+      "base64" => Ok(KeyFormat::Base64),
+      "raw" => Ok(KeyFormat::Raw),
+      _ => Err(anyhow!("Unknown key format: {}", s)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  static KEY: &str = "200a0e90e538d17390c8c4bc3bc71e44";
+
+  #[test]
+  fn test_convert_between_formats() -> anyhow::Result<()> {
+    let raw = KeyFormat::Hex.decode(KEY.as_bytes())?;
+
+    for format in [KeyFormat::Hex, KeyFormat::Base64, KeyFormat::Raw] {
+      let encoded = format.encode(&raw);
+
+      assert_eq!(raw, format.decode(&encoded)?);
+    }
+
+    assert_eq!(16, raw.len());
+    assert_eq!(KEY.as_bytes(), KeyFormat::Hex.encode(&raw).as_slice());
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_decode_ignores_surrounding_whitespace() -> anyhow::Result<()> {
+    assert_eq!(
+      KeyFormat::Hex.decode(KEY.as_bytes())?,
+      KeyFormat::Hex.decode(format!("{}\n", KEY).as_bytes())?
+    );
+    assert_eq!(b" \n".to_vec(), KeyFormat::Raw.decode(b" \n")?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_decode_rejects_invalid_keys() {
+    assert!(KeyFormat::Hex.decode(b"not hex").is_err());
+    assert!(KeyFormat::Base64.decode(b"not base64!").is_err());
+  }
+
+  #[test]
+  fn test_from_str_and_display() {
+    for format in [KeyFormat::Hex, KeyFormat::Base64, KeyFormat::Raw] {
+      assert_eq!(format, format.to_string().parse().unwrap());
+    }
+
+    assert!("pem".parse::<KeyFormat>().is_err());
+  }
+}
@@ -0,0 +1,150 @@
+use crate::encryption::Digest;
+
+/// Derives keys from a secret the same way `ActiveSupport::KeyGenerator` does,
+/// using PBKDF2 with a salt per purpose. This lets you derive the keys Rails uses
+/// for cookies and messages from nothing but your application's `secret_key_base`.
+///
+/// # Examples
+///
+/// ```
+/// use street_cred::{Digest, KeyGenerator};
+///
+/// let generator = KeyGenerator::new("secret_key_base")
+///   .with_iterations(1000)
+///   .with_digest(Digest::Sha256);
+/// let key = generator.generate_key("authenticated encrypted cookie", 32);
+///
+/// assert_eq!(
+///   "0066644521c44a0e77672fb9803a00d41059d36d1cdb9a9db2d8cef7d9e91358",
+///   key
+/// );
+/// ```
+pub struct KeyGenerator {
+  secret: String,
+  iterations: u32,
+  digest: Digest,
+}
+
+impl KeyGenerator {
+  /// Number of PBKDF2 rounds Rails uses unless told otherwise.
+  pub const DEFAULT_ITERATIONS: u32 = 65536;
+
+  /// Create a new instance of KeyGenerator. Iterations default to 65536 and
+  /// the digest defaults to SHA1, matching `ActiveSupport::KeyGenerator`.
+  ///
+  /// # Arguments
+  /// * `secret` - Secret to derive keys from, usually `secret_key_base`
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::KeyGenerator;
+  ///
+  /// let generator = KeyGenerator::new("secret_key_base");
+  /// ```
+  pub fn new(secret: &str) -> Self {
+    KeyGenerator {
+      secret: secret.to_string(),
+      iterations: Self::DEFAULT_ITERATIONS,
+      digest: Digest::default(),
+    }
+  }
+
+  /// Set the number of PBKDF2 rounds used when deriving keys. Rails uses 1000
+  /// for the application key generator that protects cookies.
+  ///
+  /// # Arguments
+  /// * `iterations` - Number of PBKDF2 rounds
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::KeyGenerator;
+  ///
+  /// let generator = KeyGenerator::new("secret_key_base").with_iterations(1000);
+  /// ```
+  pub fn with_iterations(mut self, iterations: u32) -> Self {
+    self.iterations = iterations;
+
+    self
+  }
+
+  /// Set the digest used by PBKDF2. Rails 7 applications default to SHA256.
+  ///
+  /// # Arguments
+  /// * `digest` - Digest to use for the derivation
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::{Digest, KeyGenerator};
+  ///
+  /// let generator = KeyGenerator::new("secret_key_base").with_digest(Digest::Sha256);
+  /// ```
+  pub fn with_digest(mut self, digest: Digest) -> Self {
+    self.digest = digest;
+
+    self
+  }
+
+  /// Derives a key for `salt` and returns it hex encoded, ready to be used with
+  /// [MessageEncryption](crate::MessageEncryption) and friends.
+  ///
+  /// # Arguments
+  /// * `salt` - Salt describing the purpose of the key, e.g. "authenticated encrypted cookie"
+  /// * `length` - Length of the key in bytes
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::KeyGenerator;
+  ///
+  /// let generator = KeyGenerator::new("secret_key_base").with_iterations(1000);
+  /// let key = generator.generate_key("authenticated encrypted cookie", 32);
+  ///
+  /// assert_eq!(64, key.len());
+  /// ```
+  pub fn generate_key(&self, salt: &str, length: usize) -> String {
+    let mut key = vec![0; length];
+
+    self.digest.pbkdf2(
+      self.secret.as_bytes(),
+      salt.as_bytes(),
+      self.iterations,
+      &mut key,
+    );
+
+    hex::encode(key)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_generate_key_with_defaults() {
+    let generator = KeyGenerator::new("secret_key_base");
+
+    assert_eq!(
+      "5674b339efb09715d7b83db7612afed40aa5cbc1240e7b5081d3bbc1958c315e50f5bd1a881af556a0d39e7c6ec7f5322541d317e1cd0d198f26deb470d99417",
+      generator.generate_key("salt", 64)
+    );
+  }
+
+  #[test]
+  fn test_generate_key_with_sha1_and_iterations() {
+    let generator = KeyGenerator::new("secret_key_base").with_iterations(1000);
+
+    assert_eq!(
+      "f131f32634d09ded0399c579217f0bc2763437e2c69d45c8900bd9d3568af199",
+      generator.generate_key("authenticated encrypted cookie", 32)
+    );
+  }
+
+  #[test]
+  fn test_generate_key_differs_per_salt() {
+    let generator = KeyGenerator::new("secret_key_base").with_iterations(1);
+
+    assert_ne!(
+      generator.generate_key("first salt", 32),
+      generator.generate_key("second salt", 32)
+    );
+  }
+}
@@ -0,0 +1,279 @@
+use anyhow::{Context, anyhow};
+use std::path::{Path, PathBuf};
+
+/// Environment variable holding the master key.
+static MASTER_KEY_VARIABLE: &str = "MASTER_KEY";
+/// Name of the key file used when no other key is found.
+static MASTER_KEY_FILE_NAME: &str = "master.key";
+
+/// Where the key of an encrypted file is read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeySource {
+  /// The `MASTER_KEY` environment variable
+  Environment,
+  /// A key file
+  File(PathBuf),
+}
+
+/// Finds the key of an encrypted file, in this order: an explicit key file,
+/// the `MASTER_KEY` environment variable, a key file named after the encrypted
+/// file like Rails' per-environment credentials (`production.key` for
+/// `production.yml.enc`), or `master.key`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use street_cred::KeyLookup;
+///
+/// let key = KeyLookup::new().key(Some("config/credentials/production.yml.enc")).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct KeyLookup {
+  key_file: Option<PathBuf>,
+  directory: PathBuf,
+}
+
+impl KeyLookup {
+  /// Create a lookup that falls back to `master.key` in the current directory.
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::KeyLookup;
+  ///
+  /// let lookup = KeyLookup::new();
+  /// ```
+  pub fn new() -> Self {
+    KeyLookup {
+      key_file: None,
+      directory: PathBuf::from("."),
+    }
+  }
+
+  /// Always read the key from `key_file`, e.g. the one given with --key-file.
+  ///
+  /// # Arguments
+  /// * `key_file` - Path of the key file
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::{KeyLookup, KeySource};
+  ///
+  /// let lookup = KeyLookup::new().with_key_file("config/production.key");
+  ///
+  /// assert_eq!(
+  ///   Some(KeySource::File("config/production.key".into())),
+  ///   lookup.source(None)
+  /// );
+  /// ```
+  pub fn with_key_file(mut self, key_file: impl Into<PathBuf>) -> Self {
+    self.key_file = Some(key_file.into());
+
+    self
+  }
+
+  /// Look for `master.key` in `directory` instead of the current directory.
+  ///
+  /// # Arguments
+  /// * `directory` - Directory holding `master.key`
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::KeyLookup;
+  ///
+  /// let lookup = KeyLookup::new().with_directory("config");
+  /// ```
+  pub fn with_directory(mut self, directory: impl Into<PathBuf>) -> Self {
+    self.directory = directory.into();
+
+    self
+  }
+
+  /// Returns where the key of `file_name` would be read from, or `None` when
+  /// there is no key.
+  ///
+  /// # Arguments
+  /// * `file_name` - Path of the encrypted file, if the key belongs to one
+  ///
+  pub fn source(&self, file_name: Option<&str>) -> Option<KeySource> {
+    if let Some(key_file) = &self.key_file {
+      return Some(KeySource::File(key_file.clone()));
+    }
+
+    if std::env::var(MASTER_KEY_VARIABLE).is_ok() {
+      return Some(KeySource::Environment);
+    }
+
+    let candidates = file_name
+      .map(Self::named_key_path)
+      .into_iter()
+      .chain([self.directory.join(MASTER_KEY_FILE_NAME)]);
+
+    for key_file_path in candidates {
+      if key_file_path.exists() {
+        let path = std::fs::canonicalize(&key_file_path).unwrap_or(key_file_path);
+
+        return Some(KeySource::File(path));
+      }
+    }
+
+    None
+  }
+
+  /// Reads the key of `file_name` from the first source found, without
+  /// surrounding whitespace like the newline ending most key files.
+  ///
+  /// # Arguments
+  /// * `file_name` - Path of the encrypted file, if the key belongs to one
+  ///
+  pub fn key(&self, file_name: Option<&str>) -> anyhow::Result<String> {
+    let key = match self.source(file_name) {
+      Some(KeySource::Environment) => std::env::var(MASTER_KEY_VARIABLE)?,
+      Some(KeySource::File(path)) => std::fs::read_to_string(&path)
+        .with_context(|| format!("Could not read key file {}", path.display()))?,
+      None => return Err(anyhow!("Could not find master key in environment or file.")),
+    };
+
+    Ok(key.trim().to_string())
+  }
+
+  /// Returns the key file named after an encrypted file.
+  ///
+  /// # Arguments
+  /// * `file_name` - Path of the encrypted file
+  ///
+  /// # Examples
+  /// ```
+  /// use std::path::PathBuf;
+  /// use street_cred::KeyLookup;
+  ///
+  /// assert_eq!(
+  ///   PathBuf::from("config/credentials/production.key"),
+  ///   KeyLookup::named_key_path("config/credentials/production.yml.enc")
+  /// );
+  /// ```
+  pub fn named_key_path(file_name: &str) -> PathBuf {
+    let path = Path::new(file_name);
+    let plaintext_path = match path.extension() {
+      Some(extension) if extension == "enc" => path.with_extension(""),
+      _ => path.to_path_buf(),
+    };
+
+    plaintext_path.with_extension("key")
+  }
+}
+
+impl Default for KeyLookup {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::encryption::file_encryptor::tests::with_env_vars;
+  use assert_fs::prelude::*;
+
+  /// A directory with `master.key`, and `production.key` next to
+  /// `production.yml.enc`.
+  fn key_directory() -> assert_fs::TempDir {
+    let temp = assert_fs::TempDir::new().unwrap();
+
+    temp.child("master.key").write_str("master\n").unwrap();
+    temp
+      .child("production.key")
+      .write_str("production")
+      .unwrap();
+    temp.child("explicit.key").write_str("explicit").unwrap();
+
+    temp
+  }
+
+  #[test]
+  fn test_key_file_comes_first() {
+    let temp = key_directory();
+    let encrypted_file = temp
+      .child("production.yml.enc")
+      .to_string_lossy()
+      .to_string();
+    let lookup = KeyLookup::new()
+      .with_directory(temp.path())
+      .with_key_file(temp.child("explicit.key").path());
+
+    with_env_vars(vec![(MASTER_KEY_VARIABLE, Some("environment"))], || {
+      assert_eq!("explicit", lookup.key(Some(&encrypted_file)).unwrap());
+    });
+  }
+
+  #[test]
+  fn test_environment_comes_before_key_files() {
+    let temp = key_directory();
+    let encrypted_file = temp
+      .child("production.yml.enc")
+      .to_string_lossy()
+      .to_string();
+    let lookup = KeyLookup::new().with_directory(temp.path());
+
+    with_env_vars(vec![(MASTER_KEY_VARIABLE, Some("environment"))], || {
+      assert_eq!(
+        Some(KeySource::Environment),
+        lookup.source(Some(&encrypted_file))
+      );
+      assert_eq!("environment", lookup.key(Some(&encrypted_file)).unwrap());
+    });
+  }
+
+  #[test]
+  fn test_named_key_comes_before_master_key() {
+    let temp = key_directory();
+    let encrypted_file = temp
+      .child("production.yml.enc")
+      .to_string_lossy()
+      .to_string();
+    let other_file = temp.child("staging.yml.enc").to_string_lossy().to_string();
+    let lookup = KeyLookup::new().with_directory(temp.path());
+
+    with_env_vars(vec![(MASTER_KEY_VARIABLE, None)], || {
+      assert_eq!("production", lookup.key(Some(&encrypted_file)).unwrap());
+      assert_eq!("master", lookup.key(Some(&other_file)).unwrap());
+      assert_eq!("master", lookup.key(None).unwrap());
+    });
+  }
+
+  #[test]
+  fn test_without_any_key() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let lookup = KeyLookup::new().with_directory(temp.path());
+
+    with_env_vars(vec![(MASTER_KEY_VARIABLE, None)], || {
+      assert_eq!(None, lookup.source(None));
+      assert!(lookup.key(None).is_err());
+    });
+  }
+
+  #[test]
+  fn test_unreadable_key_file() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let lookup = KeyLookup::new().with_key_file(temp.child("missing.key").path());
+
+    assert!(
+      lookup
+        .key(None)
+        .unwrap_err()
+        .to_string()
+        .starts_with("Could not read key file")
+    );
+  }
+
+  #[test]
+  fn test_named_key_path() {
+    assert_eq!(
+      PathBuf::from("production.key"),
+      KeyLookup::named_key_path("production.yml.enc")
+    );
+    assert_eq!(
+      PathBuf::from("secrets.key"),
+      KeyLookup::named_key_path("secrets.yml")
+    );
+  }
+}
@@ -0,0 +1,240 @@
+use crate::encryption::Digest;
+use crate::serialization::RubyMarshal;
+use crate::{CipherGeneration, MessageVerifier};
+use aes::Aes256;
+use aes::cipher::{BlockModeDecrypt, BlockModeEncrypt, KeyIvInit, block_padding::Pkcs7};
+use anyhow::anyhow;
+use base64::{Engine as _, engine::general_purpose};
+
+/// A message encrypted with the legacy ActiveSupport format used by Rails
+/// before 5.2. Messages are encrypted with AES-256-CBC and then signed with
+/// an HMAC, producing contents formatted like this: "base64(data--iv)--hexdigest".
+///
+/// # Examples
+///
+/// You can create a `LegacyMessageEncryption` using the following code:
+///
+/// ```
+/// use street_cred::LegacyMessageEncryption;
+///
+/// let message = b"secret message".to_vec();
+/// let secret = "8872ebc11db3ea2ed08cc629d199b1648872ebc11db3ea2ed08cc629d199b164";
+/// let sign_secret = "200a0e90e538d17390c8c4bc3bc71e44";
+/// let encryptor = LegacyMessageEncryption::new(message, secret, sign_secret);
+/// ```
+pub struct LegacyMessageEncryption {
+  message: Vec<u8>,
+  secret: String,
+  sign_secret: String,
+  digest: Digest,
+}
+
+impl LegacyMessageEncryption {
+  /// Create a new instance of LegacyMessageEncryption. The digest used for
+  /// signing defaults to SHA1, matching Rails.
+  ///
+  /// # Arguments
+  /// * `message` - Message to be encrypted, or the signed contents to decrypt
+  /// * `secret` - 32 byte hex encoded key to use for encryption/decryption
+  /// * `sign_secret` - Hex encoded key to use for signing/verifying
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::LegacyMessageEncryption;
+  ///
+  /// let message = b"secret message".to_vec();
+  /// let secret = "8872ebc11db3ea2ed08cc629d199b1648872ebc11db3ea2ed08cc629d199b164";
+  /// let sign_secret = "200a0e90e538d17390c8c4bc3bc71e44";
+  /// let encryptor = LegacyMessageEncryption::new(message, secret, sign_secret);
+  /// ```
+  pub fn new(message: Vec<u8>, secret: &str, sign_secret: &str) -> Self {
+    LegacyMessageEncryption {
+      message,
+      secret: secret.to_string(),
+      sign_secret: sign_secret.to_string(),
+      digest: Digest::default(),
+    }
+  }
+
+  /// Set the digest used to sign and verify messages.
+  ///
+  /// # Arguments
+  /// * `digest` - Digest to use for the HMAC signature
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::{Digest, LegacyMessageEncryption};
+  ///
+  /// let message = b"secret message".to_vec();
+  /// let secret = "8872ebc11db3ea2ed08cc629d199b1648872ebc11db3ea2ed08cc629d199b164";
+  /// let sign_secret = "200a0e90e538d17390c8c4bc3bc71e44";
+  /// let encryptor =
+  ///   LegacyMessageEncryption::new(message, secret, sign_secret).with_digest(Digest::Sha256);
+  /// ```
+  pub fn with_digest(mut self, digest: Digest) -> Self {
+    self.digest = digest;
+
+    self
+  }
+
+  /// Verifies and decrypts the contents of the `LegacyMessageEncryption` and
+  /// returns them as a `String`
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::LegacyMessageEncryption;
+  ///
+  /// let signed_message = b"VmZwV3VYZ2pEQUlJTzFxc3Evb1pEYTQ3OWYzQy9YdW1xOWw5clVHWjdiUW4xVUNoaVV4NnA0dUdrRVNSQmU5bS0tQUFFQ0F3UUZCZ2NJQ1FvTERBME9Edz09--2f6acee0b9ecba5ee269df6efb764970af83fa8c".to_vec();
+  /// let secret = "8872ebc11db3ea2ed08cc629d199b1648872ebc11db3ea2ed08cc629d199b164";
+  /// let sign_secret = "200a0e90e538d17390c8c4bc3bc71e44";
+  ///
+  /// let decryptor = LegacyMessageEncryption::new(signed_message, secret, sign_secret);
+  ///
+  /// assert_eq!("Peanut Butter Jelly Time", decryptor.decrypt().unwrap());
+  /// ```
+  pub fn decrypt(&self) -> anyhow::Result<String> {
+    let contents = std::str::from_utf8(&self.message)?;
+    let blob = self.verifier().verify_raw(contents)?;
+    let blob = std::str::from_utf8(&blob)?;
+
+    if let Some((message, iv)) = blob.split_once("--")
+      && let (Ok(key), Ok(message), Ok(iv)) = (
+        hex::decode(&self.secret),
+        general_purpose::STANDARD.decode(message),
+        general_purpose::STANDARD.decode(iv),
+      )
+      && let Ok(decipher) = cbc::Decryptor::<Aes256>::new_from_slices(&key, &iv)
+      && let Ok(plaintext) = decipher.decrypt_padded_vec::<Pkcs7>(&message)
+    {
+      let content = RubyMarshal::deserialize(plaintext)?;
+
+      return Ok(String::from_utf8(content)?);
+    }
+
+    Err(anyhow!("Decryption not successful"))
+  }
+
+  /// Encrypts and signs the contents of the `LegacyMessageEncryption` and
+  /// returns them as a `String`
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::LegacyMessageEncryption;
+  ///
+  /// let plaintext_message = b"super secret message".to_vec();
+  /// let secret = "8872ebc11db3ea2ed08cc629d199b1648872ebc11db3ea2ed08cc629d199b164";
+  /// let sign_secret = "200a0e90e538d17390c8c4bc3bc71e44";
+  /// let encryptor = LegacyMessageEncryption::new(plaintext_message, secret, sign_secret);
+  /// let encrypted_contents = encryptor.encrypt();
+  ///
+  /// match encrypted_contents {
+  ///   Ok(contents) => println!("Encrypted Contents: {}", contents),
+  ///   Err(why) => println!("Error: {}", why),
+  /// }
+  /// ```
+  pub fn encrypt(&self) -> anyhow::Result<String> {
+    if let Ok(key) = hex::decode(&self.secret) {
+      let random_iv = CipherGeneration::random_bytes(16);
+
+      if let Ok(cipher) = cbc::Encryptor::<Aes256>::new_from_slices(&key, &random_iv) {
+        let serialized_message = RubyMarshal::serialize(std::str::from_utf8(&self.message)?)?;
+        let encrypted = cipher.encrypt_padded_vec::<Pkcs7>(&serialized_message);
+
+        let blob = format!(
+          "{}--{}",
+          general_purpose::STANDARD.encode(encrypted),
+          general_purpose::STANDARD.encode(&random_iv)
+        );
+
+        return self.verifier().sign_raw(blob.as_bytes());
+      }
+    }
+
+    Err(anyhow!("Encryption not successful"))
+  }
+
+  fn verifier(&self) -> MessageVerifier {
+    MessageVerifier::new(&self.sign_secret).with_digest(self.digest)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  static SECRET: &str = "8872ebc11db3ea2ed08cc629d199b1648872ebc11db3ea2ed08cc629d199b164";
+  static SIGN_SECRET: &str = "200a0e90e538d17390c8c4bc3bc71e44";
+  static SHA256_MESSAGE: &str = "VmZwV3VYZ2pEQUlJTzFxc3Evb1pEYTQ3OWYzQy9YdW1xOWw5clVHWjdiUW4xVUNoaVV4NnA0dUdrRVNSQmU5bS0tQUFFQ0F3UUZCZ2NJQ1FvTERBME9Edz09--8a1cd7db1de1de261cc6529c204374f3298ae61885a0516e3515e3f931e5e49a";
+
+  #[test]
+  fn test_encrypt_decrypt_cycle() -> anyhow::Result<()> {
+    let plaintext_message = "banana: true
+apple: false
+orange: false";
+
+    for digest in [Digest::Sha1, Digest::Sha256] {
+      let encryptor =
+        LegacyMessageEncryption::new(plaintext_message.as_bytes().to_vec(), SECRET, SIGN_SECRET)
+          .with_digest(digest);
+      let encrypted = encryptor.encrypt()?;
+
+      let decryptor = LegacyMessageEncryption::new(encrypted.into_bytes(), SECRET, SIGN_SECRET)
+        .with_digest(digest);
+
+      assert_eq!(plaintext_message, decryptor.decrypt()?);
+    }
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_decrypt_with_sha256_digest() -> anyhow::Result<()> {
+    let decryptor =
+      LegacyMessageEncryption::new(SHA256_MESSAGE.as_bytes().to_vec(), SECRET, SIGN_SECRET)
+        .with_digest(Digest::Sha256);
+
+    assert_eq!("Peanut Butter Jelly Time", decryptor.decrypt()?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_decrypt_fails_with_wrong_digest() {
+    let decryptor =
+      LegacyMessageEncryption::new(SHA256_MESSAGE.as_bytes().to_vec(), SECRET, SIGN_SECRET);
+
+    assert!(decryptor.decrypt().is_err());
+  }
+
+  #[test]
+  fn test_decrypt_fails_with_tampered_message() {
+    let tampered = SHA256_MESSAGE.replacen('V', "W", 1);
+    let decryptor = LegacyMessageEncryption::new(tampered.into_bytes(), SECRET, SIGN_SECRET)
+      .with_digest(Digest::Sha256);
+
+    assert!(decryptor.decrypt().is_err());
+  }
+
+  #[test]
+  fn test_decrypt_fails_with_wrong_secret() {
+    let secret = "94b6b40cabf62ee59c9aa13a86f0e7d794b6b40cabf62ee59c9aa13a86f0e7d7";
+    let decryptor =
+      LegacyMessageEncryption::new(SHA256_MESSAGE.as_bytes().to_vec(), secret, SIGN_SECRET)
+        .with_digest(Digest::Sha256);
+
+    assert!(decryptor.decrypt().is_err());
+  }
+
+  #[test]
+  fn test_encryption_fails_with_short_secret() {
+    let encryptor = LegacyMessageEncryption::new(
+      b"secret".to_vec(),
+      "8872ebc11db3ea2ed08cc629d199b164",
+      SIGN_SECRET,
+    );
+
+    assert!(encryptor.encrypt().is_err());
+  }
+}
@@ -0,0 +1,264 @@
+use crate::encryption::MessageEncryption;
+use std::io::{self, Read, Write};
+
+/// Writer returned by [MessageEncryption::encrypting_writer]. Written bytes are
+/// buffered in memory, and encrypted into a "message--iv--tag" envelope when
+/// [EncryptingWriter::finish] is called, or when the writer is dropped. Nothing
+/// is written to the inner writer before that.
+///
+/// Dropping the writer finishes it on a best-effort basis, like flate2's
+/// encoders do, so writers owned by e.g. a `tar::Builder` still produce their
+/// envelope. Errors are lost when dropping though, so call
+/// [EncryptingWriter::finish] whenever possible.
+pub struct EncryptingWriter<W: Write> {
+  encryptor: MessageEncryption,
+  inner: Option<W>,
+  buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+  pub(crate) fn new(encryptor: MessageEncryption, inner: W) -> Self {
+    EncryptingWriter {
+      encryptor,
+      inner: Some(inner),
+      buffer: Vec::new(),
+    }
+  }
+
+  /// Encrypts everything written so far, writes the envelope to the inner
+  /// writer, flushes it and returns it.
+  pub fn finish(mut self) -> anyhow::Result<W> {
+    let mut inner = self
+      .inner
+      .take()
+      .ok_or_else(|| anyhow::anyhow!("Writer already finished"))?;
+
+    self.write_envelope(&mut inner)?;
+
+    Ok(inner)
+  }
+
+  fn write_envelope(&self, inner: &mut W) -> anyhow::Result<()> {
+    let envelope = self.encryptor.encrypt_raw(&self.buffer)?;
+
+    inner.write_all(envelope.as_bytes())?;
+    inner.flush()?;
+
+    Ok(())
+  }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+  fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+    self.buffer.extend_from_slice(data);
+
+    Ok(data.len())
+  }
+
+  /// Flushes the inner writer. The buffered data can only be encrypted as a
+  /// whole, so it isn't written before the writer is finished.
+  fn flush(&mut self) -> io::Result<()> {
+    match self.inner.as_mut() {
+      Some(inner) => inner.flush(),
+      None => Ok(()),
+    }
+  }
+}
+
+impl<W: Write> Drop for EncryptingWriter<W> {
+  fn drop(&mut self) {
+    if let Some(mut inner) = self.inner.take() {
+      let _ = self.write_envelope(&mut inner);
+    }
+  }
+}
+
+/// Reader returned by [MessageEncryption::decrypting_reader]. The whole envelope
+/// is read and decrypted on the first read, so no unauthenticated data is ever
+/// returned. When decryption fails, every read returns the error.
+pub struct DecryptingReader<R: Read> {
+  state: ReaderState,
+  inner: R,
+  position: usize,
+}
+
+enum ReaderState {
+  Encrypted(MessageEncryption),
+  Decrypted(Vec<u8>),
+  Failed(String),
+}
+
+impl<R: Read> DecryptingReader<R> {
+  pub(crate) fn new(decryptor: MessageEncryption, inner: R) -> Self {
+    DecryptingReader {
+      state: ReaderState::Encrypted(decryptor),
+      inner,
+      position: 0,
+    }
+  }
+
+  fn decrypt(&mut self, decryptor: MessageEncryption) -> anyhow::Result<Vec<u8>> {
+    let mut contents = String::new();
+
+    self.inner.read_to_string(&mut contents)?;
+
+    let split_contents = MessageEncryption::split_encrypted_contents(contents.trim())?;
+    let mut decryptor = decryptor.with_message(split_contents[0].as_bytes().to_vec());
+
+    if let Some(cipher) = split_contents.get(3) {
+      decryptor = decryptor.with_cipher(cipher.parse()?);
+    }
+
+    decryptor.decrypt_raw(split_contents[1], split_contents[2])
+  }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+  fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+    if matches!(self.state, ReaderState::Encrypted(_)) {
+      let failed = ReaderState::Failed("Decryption not successful".to_string());
+
+      if let ReaderState::Encrypted(decryptor) = std::mem::replace(&mut self.state, failed) {
+        self.state = match self.decrypt(decryptor) {
+          Ok(plaintext) => ReaderState::Decrypted(plaintext),
+          Err(why) => ReaderState::Failed(why.to_string()),
+        };
+      }
+    }
+
+    match &self.state {
+      ReaderState::Decrypted(plaintext) => {
+        let length = buffer.len().min(plaintext.len() - self.position);
+
+        buffer[..length].copy_from_slice(&plaintext[self.position..self.position + length]);
+        self.position += length;
+
+        Ok(length)
+      }
+      ReaderState::Failed(why) => Err(io::Error::new(io::ErrorKind::InvalidData, why.clone())),
+      ReaderState::Encrypted(_) => unreachable!("the envelope is decrypted above"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::CipherGeneration;
+  use crate::encryption::Cipher;
+
+  fn encrypt(encryptor: MessageEncryption, data: &[u8]) -> Vec<u8> {
+    let mut writer = encryptor.encrypting_writer(Vec::new());
+
+    for part in data.chunks(7) {
+      writer.write_all(part).unwrap();
+    }
+
+    writer.finish().unwrap()
+  }
+
+  fn decrypt(decryptor: MessageEncryption, envelope: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decrypted = Vec::new();
+
+    decryptor
+      .decrypting_reader(envelope)
+      .read_to_end(&mut decrypted)?;
+
+    Ok(decrypted)
+  }
+
+  #[test]
+  fn test_binary_round_trip() {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+    let data: Vec<u8> = (0..=255).collect();
+    let envelope = encrypt(MessageEncryption::new(Vec::new(), key, "aad"), &data);
+
+    assert_eq!(
+      data,
+      decrypt(MessageEncryption::new(Vec::new(), key, "aad"), &envelope).unwrap()
+    );
+    assert!(decrypt(MessageEncryption::new(Vec::new(), key, "other"), &envelope).is_err());
+  }
+
+  #[test]
+  fn test_dropping_the_writer_writes_the_envelope() {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+    let mut envelope = Vec::new();
+
+    {
+      let mut writer = MessageEncryption::new(Vec::new(), key, "").encrypting_writer(&mut envelope);
+
+      writer.write_all(b"dropped").unwrap();
+      writer.flush().unwrap();
+    }
+
+    assert_eq!(
+      b"dropped".to_vec(),
+      decrypt(MessageEncryption::new(Vec::new(), key, ""), &envelope).unwrap()
+    );
+  }
+
+  #[test]
+  fn test_envelope_matches_raw_encryption() {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+    let envelope = encrypt(MessageEncryption::new(Vec::new(), key, ""), b"");
+    let envelope = String::from_utf8(envelope).unwrap();
+    let split_data = MessageEncryption::split_encrypted_contents(&envelope).unwrap();
+
+    assert_eq!(3, split_data.len());
+    assert_eq!(
+      b"".to_vec(),
+      MessageEncryption::new(split_data[0].as_bytes().to_vec(), key, "")
+        .decrypt_raw(split_data[1], split_data[2])
+        .unwrap()
+    );
+  }
+
+  #[test]
+  fn test_reader_uses_recorded_cipher_and_rotated_keys() {
+    let old_key = CipherGeneration::random_key_for(Cipher::Aes256GcmSiv);
+    let new_key = CipherGeneration::random_key_for(Cipher::Aes256GcmSiv);
+    let envelope = encrypt(
+      MessageEncryption::new(Vec::new(), &old_key, "").with_cipher(Cipher::Aes256GcmSiv),
+      b"rotated",
+    );
+    let decryptor = MessageEncryption::new(Vec::new(), &new_key, "").rotate(&old_key);
+
+    assert_eq!(b"rotated".to_vec(), decrypt(decryptor, &envelope).unwrap());
+  }
+
+  #[test]
+  fn test_reader_keeps_returning_authentication_failures() {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+    let envelope = encrypt(MessageEncryption::new(Vec::new(), key, "aad"), b"secret");
+    let mut reader =
+      MessageEncryption::new(Vec::new(), key, "other").decrypting_reader(&envelope[..]);
+    let mut buffer = [0; 16];
+
+    for _ in 0..2 {
+      let error = reader.read(&mut buffer).unwrap_err();
+
+      assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+  }
+
+  #[test]
+  fn test_reader_rejects_invalid_envelopes() {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+
+    assert!(
+      decrypt(
+        MessageEncryption::new(Vec::new(), key, ""),
+        b"not encrypted"
+      )
+      .is_err()
+    );
+    assert!(
+      decrypt(
+        MessageEncryption::new(Vec::new(), key, ""),
+        b"YQ==--YQ==--YQ=="
+      )
+      .is_err()
+    );
+  }
+}
@@ -1,11 +1,11 @@
 use crate::CipherGeneration;
+use crate::encryption::metadata::Metadata;
+use crate::encryption::{Cipher, Compression, DecryptingReader, EncryptingWriter};
 use crate::serialization::RubyMarshal;
-use aes_gcm::{
-  Aes128Gcm,
-  aead::{Aead, KeyInit, Nonce},
-};
 use anyhow::anyhow;
 use base64::{Engine as _, engine::general_purpose};
+use std::io::{Read, Write};
+use std::time::SystemTime;
 
 /// A storage container that represents a message you want to encrypt/decrypt.
 /// In order for both operations to work, you also need to store the encryption key
@@ -25,8 +25,12 @@ use base64::{Engine as _, engine::general_purpose};
 /// ```
 pub struct MessageEncryption {
   message: Vec<u8>,
-  key: String,
+  keys: Vec<String>,
   aad: String,
+  deterministic: bool,
+  compression: Compression,
+  compression_threshold: usize,
+  cipher: Option<Cipher>,
 }
 
 impl MessageEncryption {
@@ -34,7 +38,8 @@ impl MessageEncryption {
   ///
   /// # Arguments
   /// * `message` - Message to be encrypted
-  /// * `key` - Key to use for encryption/decryption
+  /// * `key` - Hex encoded 16 or 32 byte key to use for encryption/decryption. The
+  ///   key length selects between AES-128-GCM and AES-256-GCM.
   /// * `aad` - Additional authenticated data
   ///
   /// # Examples
@@ -49,11 +54,133 @@ impl MessageEncryption {
   pub fn new(message: Vec<u8>, key: &str, aad: &str) -> Self {
     MessageEncryption {
       message,
-      key: key.to_string(),
+      keys: vec![key.to_string()],
       aad: aad.to_string(),
+      deterministic: false,
+      compression: Compression::None,
+      compression_threshold: 0,
+      cipher: None,
     }
   }
 
+  /// Set the cipher used to encrypt/decrypt messages. By default AES-128-GCM or
+  /// AES-256-GCM is picked from the key length, like Rails does.
+  ///
+  /// Ciphers Rails can't read are recorded as a fourth segment of the encrypted
+  /// contents: "message--iv--tag--cipher". Messages encrypted with
+  /// XChaCha20-Poly1305 are also recognized by their 24 byte nonce, so decrypting
+  /// them doesn't require setting the cipher. Messages encrypted with
+  /// AES-256-GCM-SIV can't be told apart from AES-GCM ones, so the cipher has to
+  /// be set to decrypt them.
+  ///
+  /// # Arguments
+  /// * `cipher` - Cipher to use. Its key length must match the length of the keys.
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::{Cipher, CipherGeneration, MessageEncryption};
+  ///
+  /// let key = CipherGeneration::random_key_for(Cipher::XChaCha20Poly1305);
+  /// let encryptor = MessageEncryption::new(b"secret message".to_vec(), &key, "")
+  ///   .with_cipher(Cipher::XChaCha20Poly1305);
+  /// let encrypted_contents = encryptor.encrypt().unwrap();
+  ///
+  /// assert!(encrypted_contents.ends_with("--xchacha20-poly1305"));
+  /// ```
+  pub fn with_cipher(mut self, cipher: Cipher) -> Self {
+    self.cipher = Some(cipher);
+
+    self
+  }
+
+  /// Compress messages larger than `threshold` bytes before encrypting them.
+  /// Compression is only kept when it actually makes the message smaller.
+  /// Compressed messages are detected automatically when decrypting, whether or
+  /// not compression was configured on the decrypting side.
+  ///
+  /// Note that Rails can't read compressed messages.
+  ///
+  /// # Arguments
+  /// * `compression` - Compression to apply
+  /// * `threshold` - Size in bytes above which messages are compressed
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::{Compression, MessageEncryption};
+  ///
+  /// let key = "8872ebc11db3ea2ed08cc629d199b164";
+  /// let message = "-----BEGIN CERTIFICATE-----\n".repeat(100).into_bytes();
+  /// let encryptor = MessageEncryption::new(message, key, "").with_compression(Compression::Zstd, 1024);
+  /// ```
+  pub fn with_compression(mut self, compression: Compression, threshold: usize) -> Self {
+    self.compression = compression;
+    self.compression_threshold = threshold;
+
+    self
+  }
+
+  /// Opt into deterministic encryption, where the same message encrypted with
+  /// the same key and additional authenticated data always produces the same
+  /// result. Instead of a random IV, the IV is derived from the bytes that are
+  /// encrypted the way Rails derives it: the first 12 bytes (as many as the
+  /// cipher's nonce needs) of an HMAC-SHA256 of them, keyed with the encryption
+  /// key (see [CipherGeneration::deterministic_iv]).
+  ///
+  /// # Interoperability
+  ///
+  /// The bytes encrypted are the serialized message, so the result only matches
+  /// what Rails produces for the same bytes. Rails' `MessageEncryptor` has no
+  /// deterministic mode; use [crate::ActiveRecordEncryption] for values Rails
+  /// encrypts deterministically. With additional authenticated data, which Rails
+  /// never uses for deterministic encryption, the AAD is part of the HMAC input
+  /// too, so equal messages with different AADs never share an IV. Those IVs
+  /// have no Rails equivalent.
+  ///
+  /// # Security
+  ///
+  /// Deterministic encryption reveals which messages are equal to anyone who can
+  /// see the encrypted results. Only use it when you need to look up or compare
+  /// encrypted values, and never for low-entropy messages an attacker could guess.
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::MessageEncryption;
+  ///
+  /// let key = "8872ebc11db3ea2ed08cc629d199b164";
+  /// let encryptor = MessageEncryption::new(b"jorge@example.com".to_vec(), key, "").deterministic();
+  ///
+  /// assert_eq!(encryptor.encrypt().unwrap(), encryptor.encrypt().unwrap());
+  /// ```
+  pub fn deterministic(mut self) -> Self {
+    self.deterministic = true;
+
+    self
+  }
+
+  /// Add a fallback key to try when decrypting, like
+  /// `ActiveSupport::MessageEncryptor#rotate`. Messages are always encrypted with
+  /// the key given to [MessageEncryption::new], while decryption tries every key
+  /// in the order they were added. This allows accepting both an old and a new
+  /// key while a key is being rolled out.
+  ///
+  /// # Arguments
+  /// * `key` - Hex encoded key to fall back to when decrypting
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::MessageEncryption;
+  ///
+  /// let message = b"secret message".to_vec();
+  /// let new_key = "8872ebc11db3ea2ed08cc629d199b164";
+  /// let old_key = "425D76994EE6101105DDDA2EE2604AA0";
+  /// let encryptor = MessageEncryption::new(message, new_key, "").rotate(old_key);
+  /// ```
+  pub fn rotate(mut self, key: &str) -> Self {
+    self.keys.push(key.to_string());
+
+    self
+  }
+
   /// Decrypts the contents of the `MessageEncryption` and returns them as a `String`
   ///
   /// # Arguments
@@ -81,34 +208,10 @@ impl MessageEncryption {
   /// }
   /// ```
   pub fn decrypt(&self, iv: &str, tag: &str) -> anyhow::Result<String> {
-    if let (Ok(key), Ok(iv), Ok(message), Ok(tag)) = (
-      hex_to_bytes(&self.key),
-      general_purpose::STANDARD.decode(iv),
-      general_purpose::STANDARD.decode(&self.message),
-      general_purpose::STANDARD.decode(tag),
-    ) && let (Ok(decipher), Ok(nonce)) = (
-      Aes128Gcm::new_from_slice(&key),
-      Nonce::<Aes128Gcm>::try_from(iv.as_slice()),
-    ) {
-      let mut ciphertext = message;
-
-      ciphertext.extend_from_slice(&tag);
-
-      let payload = aes_gcm::aead::Payload {
-        msg: &ciphertext,
-        aad: self.aad.as_bytes(),
-      };
-
-      let plaintext = decipher.decrypt(&nonce, payload);
+    let (plaintext, _key_index) = self.decrypt_serialized(iv, tag)?;
+    let content = RubyMarshal::deserialize(plaintext)?;
 
-      if let Ok(plaintext) = plaintext {
-        let content = RubyMarshal::deserialize(plaintext)?;
-
-        return Ok(String::from_utf8(content)?);
-      }
-    }
-
-    Err(anyhow!("Decryption not successful"))
+    Ok(String::from_utf8(content)?)
   }
 
   /// Encrypts the contents of the `MessageEncryption` and returns them as a `String`
@@ -130,49 +233,202 @@ impl MessageEncryption {
   /// }
   /// ```
   pub fn encrypt(&self) -> anyhow::Result<String> {
-    if let Ok(key) = hex_to_bytes(&self.key) {
-      let random_iv = CipherGeneration::random_iv();
+    let serialized_message = RubyMarshal::serialize(std::str::from_utf8(&self.message)?)?;
 
-      if let (Ok(cipher), Ok(nonce)) = (
-        Aes128Gcm::new_from_slice(&key),
-        Nonce::<Aes128Gcm>::try_from(random_iv.as_slice()),
-      ) {
-        let serialized_message = RubyMarshal::serialize(std::str::from_utf8(&self.message)?)?;
+    self.encrypt_serialized(&serialized_message)
+  }
 
-        let payload = aes_gcm::aead::Payload {
-          msg: &serialized_message,
-          aad: self.aad.as_bytes(),
-        };
+  /// Decrypts the contents of the `MessageEncryption` and returns them as a `String`,
+  /// along with the index of the key that decrypted them. Index `0` is the key given
+  /// to [MessageEncryption::new], followed by the keys added with
+  /// [MessageEncryption::rotate] in order.
+  ///
+  /// # Arguments
+  ///
+  /// * `iv` - Initialization vector used when initially encrypting the message
+  /// * `tag` - Additional Authenticated data resulting from encrypting the message
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::MessageEncryption;
+  ///
+  /// let old_key = "425d76994ee6101105ddda2ee2604aa0";
+  /// let new_key = "8872ebc11db3ea2ed08cc629d199b164";
+  /// let encryptor = MessageEncryption::new(b"secret message".to_vec(), old_key, "");
+  /// let encrypted_contents = encryptor.encrypt().unwrap();
+  ///
+  /// let split_data = MessageEncryption::split_encrypted_contents(&encrypted_contents).unwrap();
+  /// let decryptor =
+  ///   MessageEncryption::new(split_data[0].as_bytes().to_vec(), new_key, "").rotate(old_key);
+  /// let (contents, key_index) = decryptor
+  ///   .decrypt_with_key_index(split_data[1], split_data[2])
+  ///   .unwrap();
+  ///
+  /// assert_eq!("secret message", contents);
+  /// assert_eq!(1, key_index);
+  /// ```
+  pub fn decrypt_with_key_index(&self, iv: &str, tag: &str) -> anyhow::Result<(String, usize)> {
+    let (plaintext, key_index) = self.decrypt_serialized(iv, tag)?;
+    let content = RubyMarshal::deserialize(plaintext)?;
 
-        let encrypted = cipher.encrypt(&nonce, payload);
+    Ok((String::from_utf8(content)?, key_index))
+  }
 
-        if let Ok(encrypted) = encrypted {
-          let (ct, tag) = encrypted.split_at(encrypted.len() - 16);
+  /// Decrypts the contents of the `MessageEncryption`, checking they were
+  /// encrypted for `purpose` and have not expired, and returns them as a `String`.
+  /// Messages are expected in the Rails metadata envelope
+  /// `{"_rails": {"message": ..., "exp": ..., "pur": ...}}`.
+  ///
+  /// # Arguments
+  ///
+  /// * `iv` - Initialization vector used when initially encrypting the message
+  /// * `tag` - Additional Authenticated data resulting from encrypting the message
+  /// * `purpose` - Purpose the message is expected to have been encrypted for
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::MessageEncryption;
+  ///
+  /// let key = "8872ebc11db3ea2ed08cc629d199b164";
+  /// let encryptor = MessageEncryption::new(b"reset password".to_vec(), key, "");
+  /// let encrypted_contents = encryptor.encrypt_with(Some("password_reset"), None).unwrap();
+  ///
+  /// let split_data = MessageEncryption::split_encrypted_contents(&encrypted_contents).unwrap();
+  /// let decryptor = MessageEncryption::new(split_data[0].as_bytes().to_vec(), key, "");
+  ///
+  /// assert!(decryptor.decrypt_for(split_data[1], split_data[2], Some("login")).is_err());
+  /// assert_eq!(
+  ///   "reset password",
+  ///   decryptor.decrypt_for(split_data[1], split_data[2], Some("password_reset")).unwrap()
+  /// );
+  /// ```
+  pub fn decrypt_for(&self, iv: &str, tag: &str, purpose: Option<&str>) -> anyhow::Result<String> {
+    let (plaintext, _key_index) = self.decrypt_serialized(iv, tag)?;
+    let serialized = Metadata::verify(&plaintext, purpose)?;
 
-          let encryption_result = format!(
-            "{}--{}--{}",
-            general_purpose::STANDARD.encode(ct),
-            general_purpose::STANDARD.encode(&random_iv),
-            general_purpose::STANDARD.encode(tag)
-          );
+    if serialized.starts_with(b"\x04\x08") {
+      let content = RubyMarshal::deserialize(serialized)?;
 
-          return Ok(encryption_result);
-        }
-      }
+      Ok(String::from_utf8(content)?)
+    } else {
+      Ok(serde_json::from_slice(&serialized)?)
     }
+  }
 
-    Err(anyhow!("Encryption not successful"))
+  /// Encrypts the contents of the `MessageEncryption` for a given purpose and
+  /// expiry, and returns them as a `String`. The result can only be decrypted
+  /// with [MessageEncryption::decrypt_for] using the same purpose, before it
+  /// expires. This is wire compatible with `encrypt_and_sign(purpose:, expires_at:)`
+  /// in Rails.
+  ///
+  /// # Arguments
+  ///
+  /// * `purpose` - Purpose the message is encrypted for
+  /// * `expires_at` - Point in time after which the message is no longer valid
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use std::time::{Duration, SystemTime};
+  /// use street_cred::MessageEncryption;
+  ///
+  /// let key = "8872ebc11db3ea2ed08cc629d199b164";
+  /// let encryptor = MessageEncryption::new(b"reset password".to_vec(), key, "");
+  /// let expires_at = SystemTime::now() + Duration::from_secs(15 * 60);
+  ///
+  /// let encrypted_contents = encryptor.encrypt_with(Some("password_reset"), Some(expires_at));
+  ///
+  /// assert!(encrypted_contents.is_ok());
+  /// ```
+  pub fn encrypt_with(
+    &self,
+    purpose: Option<&str>,
+    expires_at: Option<SystemTime>,
+  ) -> anyhow::Result<String> {
+    let serialized_message = RubyMarshal::serialize(std::str::from_utf8(&self.message)?)?;
+    let wrapped = Metadata::wrap(&serialized_message, purpose, expires_at);
+
+    self.encrypt_serialized(&wrapped)
+  }
+
+  /// Returns a writer that collects everything written to it and, once
+  /// [EncryptingWriter::finish] is called, encrypts it with this
+  /// `MessageEncryption`'s key, cipher and additional authenticated data and
+  /// writes the "message--iv--tag" envelope to `inner`. The message passed to
+  /// [MessageEncryption::new] is ignored.
+  ///
+  /// The written bytes are encrypted as they are, without being serialized or
+  /// compressed, so any binary data can be written. This matches Rails'
+  /// `MessageEncryptor` with `serializer: ActiveSupport::MessageEncryptor::NullSerializer`.
+  ///
+  /// # Arguments
+  /// * `inner` - Writer receiving the encrypted envelope
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use std::io::Write;
+  /// use street_cred::MessageEncryption;
+  ///
+  /// let key = "8872ebc11db3ea2ed08cc629d199b164";
+  /// let mut writer = MessageEncryption::new(Vec::new(), key, "").encrypting_writer(Vec::new());
+  ///
+  /// writer.write_all(b"{\"api_key\":\"123\"}").unwrap();
+  ///
+  /// let envelope = String::from_utf8(writer.finish().unwrap()).unwrap();
+  ///
+  /// assert_eq!(3, MessageEncryption::split_encrypted_contents(&envelope).unwrap().len());
+  /// ```
+  pub fn encrypting_writer<W: Write>(self, inner: W) -> EncryptingWriter<W> {
+    EncryptingWriter::new(self, inner)
   }
 
-  /// Split contents of an encrypted file into a Vec with a length of 3.
-  /// The first index is the encrypted contents, the second index is the
-  /// initialization vector, and the third index is the additional authenticated
-  /// data.
+  /// Returns a reader that reads a "message--iv--tag" envelope, as written by
+  /// [MessageEncryption::encrypting_writer], from `inner` and yields its
+  /// decrypted bytes. Keys added with [MessageEncryption::rotate] are tried as
+  /// well, and a cipher recorded in the envelope is used over the configured one.
+  /// The message passed to [MessageEncryption::new] is ignored.
+  ///
+  /// # Arguments
+  /// * `inner` - Reader providing the encrypted envelope
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use std::io::{Read, Write};
+  /// use street_cred::MessageEncryption;
+  ///
+  /// let key = "8872ebc11db3ea2ed08cc629d199b164";
+  /// let mut writer = MessageEncryption::new(Vec::new(), key, "").encrypting_writer(Vec::new());
+  ///
+  /// writer.write_all(b"secret message").unwrap();
+  ///
+  /// let envelope = writer.finish().unwrap();
+  /// let mut decrypted = String::new();
+  ///
+  /// MessageEncryption::new(Vec::new(), key, "")
+  ///   .decrypting_reader(envelope.as_slice())
+  ///   .read_to_string(&mut decrypted)
+  ///   .unwrap();
+  ///
+  /// assert_eq!("secret message", decrypted);
+  /// ```
+  pub fn decrypting_reader<R: Read>(self, inner: R) -> DecryptingReader<R> {
+    DecryptingReader::new(self, inner)
+  }
+
+  /// Split contents of an encrypted file into a Vec with a length of 3, or 4
+  /// when the cipher is recorded. The first index is the encrypted contents, the
+  /// second index is the initialization vector, the third index is the additional
+  /// authenticated data and the optional fourth index is the cipher.
   ///
   /// # Arguments
   ///
   /// * `contents` - The entire encrypted file as one long string. Encrypted
-  ///   contents should be formatted like this: "message--iv--aad"
+  ///   contents should be formatted like this: "message--iv--aad", or
+  ///   "message--iv--aad--cipher"
   ///
   /// # Examples
   ///
@@ -189,12 +445,143 @@ impl MessageEncryption {
       acc
     });
 
-    if contents.len() == 3 {
+    if contents.len() == 3 || contents.len() == 4 {
       Ok(contents)
     } else {
       Err(anyhow!("Invalid encrypted contents"))
     }
   }
+
+  /// Replaces the message to encrypt/decrypt, keeping keys and options.
+  pub(crate) fn with_message(mut self, message: Vec<u8>) -> Self {
+    self.message = message;
+
+    self
+  }
+
+  /// Decrypts the contents of the `MessageEncryption`, decompressing them when
+  /// needed, and returns the serialized message along with the index of the key
+  /// that decrypted it.
+  fn decrypt_serialized(&self, iv: &str, tag: &str) -> anyhow::Result<(Vec<u8>, usize)> {
+    let (plaintext, key_index) = self.decrypt_raw_with_key_index(iv, tag)?;
+
+    Ok((Compression::decompress(plaintext)?, key_index))
+  }
+
+  /// Compresses a serialized message when configured to and encrypts it.
+  fn encrypt_serialized(&self, serialized_message: &[u8]) -> anyhow::Result<String> {
+    match self
+      .compression
+      .compress_if_worth_it(serialized_message, self.compression_threshold)?
+    {
+      Some(compressed) => self.encrypt_raw(&compressed),
+      None => self.encrypt_raw(serialized_message),
+    }
+  }
+
+  /// Decrypts the contents of the `MessageEncryption` without deserializing
+  /// them and returns the plaintext bytes.
+  ///
+  /// # Arguments
+  ///
+  /// * `iv` - Initialization vector used when initially encrypting the message
+  /// * `tag` - Authentication tag resulting from encrypting the message
+  ///
+  pub(crate) fn decrypt_raw(&self, iv: &str, tag: &str) -> anyhow::Result<Vec<u8>> {
+    let (plaintext, _key_index) = self.decrypt_raw_with_key_index(iv, tag)?;
+
+    Ok(plaintext)
+  }
+
+  fn decrypt_raw_with_key_index(&self, iv: &str, tag: &str) -> anyhow::Result<(Vec<u8>, usize)> {
+    if let (Ok(iv), Ok(message), Ok(tag)) = (
+      general_purpose::STANDARD.decode(iv),
+      general_purpose::STANDARD.decode(&self.message),
+      general_purpose::STANDARD.decode(tag),
+    ) {
+      let mut ciphertext = message;
+
+      ciphertext.extend_from_slice(&tag);
+
+      for (key_index, key) in self.keys.iter().enumerate() {
+        if let Ok(key) = hex_to_bytes(key)
+          && let Ok(cipher) = self.cipher_for(&key, iv.len())
+          && let Ok(plaintext) = cipher.decrypt(&key, &iv, &ciphertext, self.aad.as_bytes())
+        {
+          return Ok((plaintext, key_index));
+        }
+      }
+    }
+
+    Err(anyhow!("Decryption not successful"))
+  }
+
+  /// Encrypts `plaintext` as is, without serializing it first, and returns
+  /// the result formatted like this: "message--iv--tag"
+  ///
+  /// # Arguments
+  ///
+  /// * `plaintext` - Bytes to encrypt
+  ///
+  pub(crate) fn encrypt_raw(&self, plaintext: &[u8]) -> anyhow::Result<String> {
+    let key = hex_to_bytes(&self.keys[0])?;
+    let cipher = self.cipher_for(&key, 0)?;
+
+    let iv = if self.deterministic {
+      CipherGeneration::deterministic_nonce(
+        &key,
+        self.aad.as_bytes(),
+        plaintext,
+        cipher.nonce_len(),
+      )
+    } else {
+      CipherGeneration::random_iv_for(cipher)
+    };
+
+    self.encrypt_raw_with_iv(plaintext, &iv)
+  }
+
+  /// Encrypts `plaintext` as is, using the given initialization vector, and
+  /// returns the result formatted like this: "message--iv--tag"
+  ///
+  /// # Arguments
+  ///
+  /// * `plaintext` - Bytes to encrypt
+  /// * `iv` - Initialization vector to encrypt with. Never reuse an IV with the same key
+  ///   for different plaintexts.
+  ///
+  pub(crate) fn encrypt_raw_with_iv(&self, plaintext: &[u8], iv: &[u8]) -> anyhow::Result<String> {
+    if let Ok(key) = hex_to_bytes(&self.keys[0])
+      && let Ok(cipher) = self.cipher_for(&key, iv.len())
+      && let Ok(encrypted) = cipher.encrypt(&key, iv, plaintext, self.aad.as_bytes())
+    {
+      let (ct, tag) = encrypted.split_at(encrypted.len() - 16);
+
+      let mut encryption_result = format!(
+        "{}--{}--{}",
+        general_purpose::STANDARD.encode(ct),
+        general_purpose::STANDARD.encode(iv),
+        general_purpose::STANDARD.encode(tag)
+      );
+
+      if !cipher.is_rails_compatible() {
+        encryption_result.push_str(&format!("--{}", cipher));
+      }
+
+      return Ok(encryption_result);
+    }
+
+    Err(anyhow!("Encryption not successful"))
+  }
+
+  /// Returns the configured cipher, or picks one from the key and nonce lengths.
+  fn cipher_for(&self, key: &[u8], nonce_len: usize) -> anyhow::Result<Cipher> {
+    match self.cipher {
+      Some(cipher) => Ok(cipher),
+      None if nonce_len == Cipher::XChaCha20Poly1305.nonce_len() => Ok(Cipher::XChaCha20Poly1305),
+      None => Cipher::for_key(key),
+    }
+  }
 }
 
 fn hex_to_bytes(raw_hex: &str) -> Result<Vec<u8>, hex::FromHexError> {
@@ -287,6 +674,311 @@ orange: false";
     assert_eq!(plaintext_message, result.unwrap());
   }
 
+  #[test]
+  fn test_encryption_decryption_with_256_bit_key() {
+    let key = "8872ebc11db3ea2ed08cc629d199b1648872ebc11db3ea2ed08cc629d199b164";
+    let aad = "";
+    let plaintext_message = "banana: true";
+
+    let encryptor = MessageEncryption::new(plaintext_message.as_bytes().to_vec(), key, aad);
+    let encrypted_result = encryptor.encrypt().unwrap();
+
+    let split_data = MessageEncryption::split_encrypted_contents(&encrypted_result).unwrap();
+    let decryptor = MessageEncryption::new(split_data[0].as_bytes().to_vec(), key, aad);
+
+    let result = decryptor.decrypt(split_data[1], split_data[2]);
+
+    assert_eq!(plaintext_message, result.unwrap());
+  }
+
+  #[test]
+  fn test_decrypt_for_rails_message() {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+    let messages = [
+      "738l2NpsmgUvpVyvkZntbblBw07gztt62WDQDRepiHOMoQlPjxpcNsXfiNjmoGRXOwV7uXN2iWidoP3jz0tWv5QnIlfbLVkT4aqO/EMKQI461R7W29oHzGwX5zdoMsE+5LM=--AAECAwQFBgcICQoL--Me2sXcWkcfkiCYPF1y31Iw==",
+      "738l2NpsmgUvpVyvmJ3qf/ochDy/jfdOxQrbEFKMt1bqngh7sgQtDPqCib2c+11nOUsj3Gcsp0eh8uLJ--AAECAwQFBgcICQoL--ju71GS6oFSaTZ1/FZGjLEQ==",
+    ];
+
+    for message in messages {
+      let split_data = MessageEncryption::split_encrypted_contents(message).unwrap();
+      let decryptor = MessageEncryption::new(split_data[0].as_bytes().to_vec(), key, "");
+
+      let result = decryptor.decrypt_for(split_data[1], split_data[2], Some("login"));
+
+      assert_eq!("Peanut Butter Jelly Time", result.unwrap());
+      assert!(decryptor.decrypt(split_data[1], split_data[2]).is_err());
+    }
+  }
+
+  #[test]
+  fn test_decrypt_for_rejects_expired_message() {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+    let expires_at = SystemTime::now() - std::time::Duration::from_secs(1);
+    let encryptor = MessageEncryption::new(b"banana: true".to_vec(), key, "");
+
+    let encrypted_result = encryptor.encrypt_with(None, Some(expires_at)).unwrap();
+
+    let split_data = MessageEncryption::split_encrypted_contents(&encrypted_result).unwrap();
+    let decryptor = MessageEncryption::new(split_data[0].as_bytes().to_vec(), key, "");
+
+    assert!(
+      decryptor
+        .decrypt_for(split_data[1], split_data[2], None)
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn test_encrypt_with_without_metadata_matches_encrypt() {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+    let encryptor = MessageEncryption::new(b"banana: true".to_vec(), key, "");
+
+    let encrypted_result = encryptor.encrypt_with(None, None).unwrap();
+
+    let split_data = MessageEncryption::split_encrypted_contents(&encrypted_result).unwrap();
+    let decryptor = MessageEncryption::new(split_data[0].as_bytes().to_vec(), key, "");
+
+    assert_eq!(
+      "banana: true",
+      decryptor.decrypt(split_data[1], split_data[2]).unwrap()
+    );
+    assert_eq!(
+      "banana: true",
+      decryptor
+        .decrypt_for(split_data[1], split_data[2], None)
+        .unwrap()
+    );
+  }
+
+  #[test]
+  fn test_decryption_with_rotated_keys() {
+    let old_key = "94b6b40cabf62ee59c9aa13a86f0e7d7";
+    let new_key = "8872ebc11db3ea2ed08cc629d199b164";
+    let aad = "";
+
+    let old_encryptor = MessageEncryption::new(b"banana: true".to_vec(), old_key, aad);
+    let new_encryptor = MessageEncryption::new(b"banana: true".to_vec(), new_key, aad);
+
+    for (encryptor, expected_index) in [(old_encryptor, 1), (new_encryptor, 0)] {
+      let encrypted_result = encryptor.encrypt().unwrap();
+      let split_data = MessageEncryption::split_encrypted_contents(&encrypted_result).unwrap();
+
+      let decryptor =
+        MessageEncryption::new(split_data[0].as_bytes().to_vec(), new_key, aad).rotate(old_key);
+      let (contents, key_index) = decryptor
+        .decrypt_with_key_index(split_data[1], split_data[2])
+        .unwrap();
+
+      assert_eq!("banana: true", contents);
+      assert_eq!(expected_index, key_index);
+    }
+  }
+
+  #[test]
+  fn test_encryption_uses_first_key() {
+    let old_key = "94b6b40cabf62ee59c9aa13a86f0e7d7";
+    let new_key = "8872ebc11db3ea2ed08cc629d199b164";
+    let aad = "";
+
+    let encryptor = MessageEncryption::new(b"banana: true".to_vec(), new_key, aad).rotate(old_key);
+    let encrypted_result = encryptor.encrypt().unwrap();
+    let split_data = MessageEncryption::split_encrypted_contents(&encrypted_result).unwrap();
+
+    let decryptor = MessageEncryption::new(split_data[0].as_bytes().to_vec(), new_key, aad);
+
+    assert!(decryptor.decrypt(split_data[1], split_data[2]).is_ok());
+  }
+
+  #[test]
+  fn test_deterministic_encryption() {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+    let aad = "";
+
+    let encryptor = MessageEncryption::new(b"banana: true".to_vec(), key, aad).deterministic();
+    let other_encryptor =
+      MessageEncryption::new(b"banana: false".to_vec(), key, aad).deterministic();
+
+    let encrypted_result = encryptor.encrypt().unwrap();
+
+    assert_eq!(encrypted_result, encryptor.encrypt().unwrap());
+    assert_ne!(encrypted_result, other_encryptor.encrypt().unwrap());
+
+    let split_data = MessageEncryption::split_encrypted_contents(&encrypted_result).unwrap();
+    let decryptor = MessageEncryption::new(split_data[0].as_bytes().to_vec(), key, aad);
+
+    assert_eq!(
+      "banana: true",
+      decryptor.decrypt(split_data[1], split_data[2]).unwrap()
+    );
+  }
+
+  #[test]
+  fn test_deterministic_encryption_derives_iv_from_aad() {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+    let iv_for = |aad: &str| {
+      let encrypted = MessageEncryption::new(b"banana: true".to_vec(), key, aad)
+        .deterministic()
+        .encrypt()
+        .unwrap();
+
+      MessageEncryption::split_encrypted_contents(&encrypted).unwrap()[1].to_string()
+    };
+
+    assert_eq!(iv_for("users"), iv_for("users"));
+    assert_ne!(iv_for("users"), iv_for("accounts"));
+    assert_ne!(iv_for(""), iv_for("users"));
+  }
+
+  #[test]
+  fn test_deterministic_encryption_derives_iv_like_rails() -> anyhow::Result<()> {
+    // The deterministic key and Rails value of ActiveRecordEncryption's
+    // test_deterministic_encrypt_matches_rails.
+    let key = crate::KeyGenerator::new("deterministic-key").generate_key("derivation-salt", 32);
+    let encrypted = MessageEncryption::new(Vec::new(), &key, "")
+      .deterministic()
+      .encrypt_raw(b"jorge@example.com")?;
+
+    assert_eq!(
+      "BeQq4P1oCFuJPnDeVG9M7BQ=--iDwvYdC6NgzhAoHb--bntIaaKYhKGJeLJ/qN4bxQ==",
+      encrypted
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_non_deterministic_encryption() {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+    let encryptor = MessageEncryption::new(b"banana: true".to_vec(), key, "");
+
+    assert_ne!(encryptor.encrypt().unwrap(), encryptor.encrypt().unwrap());
+  }
+
+  #[test]
+  fn test_encryption_decryption_with_compression() {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+    let aad = "";
+    let plaintext_message = "banana: true\n".repeat(100);
+
+    for compression in [Compression::Zlib, Compression::Zstd] {
+      let encryptor = MessageEncryption::new(plaintext_message.as_bytes().to_vec(), key, aad)
+        .with_compression(compression, 140);
+      let uncompressed = MessageEncryption::new(plaintext_message.as_bytes().to_vec(), key, aad);
+
+      let encrypted_result = encryptor.encrypt().unwrap();
+
+      assert!(encrypted_result.len() < uncompressed.encrypt().unwrap().len());
+
+      let split_data = MessageEncryption::split_encrypted_contents(&encrypted_result).unwrap();
+      let decryptor = MessageEncryption::new(split_data[0].as_bytes().to_vec(), key, aad);
+
+      assert_eq!(
+        plaintext_message,
+        decryptor.decrypt(split_data[1], split_data[2]).unwrap()
+      );
+    }
+  }
+
+  #[test]
+  fn test_compression_skipped_below_threshold() {
+    let key = "8872ebc11db3ea2ed08cc629d199b164";
+    let aad = "";
+    let plaintext_message = "banana: true\n".repeat(100);
+
+    let encryptor = MessageEncryption::new(plaintext_message.as_bytes().to_vec(), key, aad)
+      .with_compression(Compression::Zstd, plaintext_message.len() * 2);
+    let encrypted_result = encryptor.encrypt().unwrap();
+
+    let split_data = MessageEncryption::split_encrypted_contents(&encrypted_result).unwrap();
+    let decryptor = MessageEncryption::new(split_data[0].as_bytes().to_vec(), key, aad);
+
+    assert!(
+      decryptor
+        .decrypt_raw(split_data[1], split_data[2])
+        .unwrap()
+        .starts_with(b"\x04\x08")
+    );
+  }
+
+  #[test]
+  fn test_encryption_decryption_with_xchacha20_poly1305() {
+    let key = "0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f9";
+    let plaintext_message = "banana: true";
+
+    let encryptor = MessageEncryption::new(plaintext_message.as_bytes().to_vec(), key, "")
+      .with_cipher(Cipher::XChaCha20Poly1305);
+    let encrypted_result = encryptor.encrypt().unwrap();
+
+    let split_data = MessageEncryption::split_encrypted_contents(&encrypted_result).unwrap();
+
+    assert_eq!(4, split_data.len());
+    assert_eq!("xchacha20-poly1305", split_data[3]);
+    assert_eq!(
+      24,
+      general_purpose::STANDARD
+        .decode(split_data[1])
+        .unwrap()
+        .len()
+    );
+
+    let decryptor = MessageEncryption::new(split_data[0].as_bytes().to_vec(), key, "");
+
+    assert_eq!(
+      plaintext_message,
+      decryptor.decrypt(split_data[1], split_data[2]).unwrap()
+    );
+
+    let aes_decryptor = MessageEncryption::new(split_data[0].as_bytes().to_vec(), key, "")
+      .with_cipher(Cipher::Aes256Gcm);
+
+    assert!(aes_decryptor.decrypt(split_data[1], split_data[2]).is_err());
+  }
+
+  #[test]
+  fn test_encryption_decryption_with_aes_256_gcm_siv() {
+    let key = "0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f9";
+    let plaintext_message = "banana: true";
+
+    let encryptor = MessageEncryption::new(plaintext_message.as_bytes().to_vec(), key, "")
+      .with_cipher(Cipher::Aes256GcmSiv);
+    let encrypted_result = encryptor.encrypt().unwrap();
+
+    let split_data = MessageEncryption::split_encrypted_contents(&encrypted_result).unwrap();
+
+    assert_eq!("aes-256-gcm-siv", split_data[3]);
+
+    let decryptor = MessageEncryption::new(split_data[0].as_bytes().to_vec(), key, "")
+      .with_cipher(split_data[3].parse().unwrap());
+
+    assert_eq!(
+      plaintext_message,
+      decryptor.decrypt(split_data[1], split_data[2]).unwrap()
+    );
+
+    let gcm_decryptor = MessageEncryption::new(split_data[0].as_bytes().to_vec(), key, "");
+
+    assert!(gcm_decryptor.decrypt(split_data[1], split_data[2]).is_err());
+  }
+
+  #[test]
+  fn test_xchacha20_poly1305_rejects_short_key() {
+    let encryptor =
+      MessageEncryption::new(b"banana".to_vec(), "8872ebc11db3ea2ed08cc629d199b164", "")
+        .with_cipher(Cipher::XChaCha20Poly1305);
+
+    assert!(encryptor.encrypt().is_err());
+  }
+
+  #[test]
+  fn test_deterministic_encryption_with_xchacha20_poly1305() {
+    let key = "0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f9";
+    let encryptor = MessageEncryption::new(b"jorge@example.com".to_vec(), key, "")
+      .with_cipher(Cipher::XChaCha20Poly1305)
+      .deterministic();
+
+    assert_eq!(encryptor.encrypt().unwrap(), encryptor.encrypt().unwrap());
+  }
+
   #[test]
   fn test_decryption_fails_with_incorrect_iv() {
     let key = "94b6b40cabf62ee59c9aa13a86f0e7d7";
@@ -0,0 +1,211 @@
+use crate::encryption::Digest;
+use crate::serialization::RubyMarshal;
+use anyhow::anyhow;
+use base64::{Engine as _, engine::general_purpose};
+
+/// Generates and verifies signed (but not encrypted) messages compatible with
+/// `ActiveSupport::MessageVerifier`. Signed messages are formatted like this:
+/// "base64(data)--hexdigest".
+///
+/// # Examples
+///
+/// ```
+/// use street_cred::MessageVerifier;
+///
+/// let verifier = MessageVerifier::new("200a0e90e538d17390c8c4bc3bc71e44");
+/// let token = verifier.generate("Peanut Butter Jelly Time").unwrap();
+///
+/// assert_eq!("Peanut Butter Jelly Time", verifier.verify(&token).unwrap());
+/// ```
+pub struct MessageVerifier {
+  secret: String,
+  digest: Digest,
+}
+
+impl MessageVerifier {
+  /// Create a new instance of MessageVerifier. The digest used for signing
+  /// defaults to SHA1, matching Rails.
+  ///
+  /// # Arguments
+  /// * `secret` - Hex encoded key to use for signing/verifying
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::MessageVerifier;
+  ///
+  /// let verifier = MessageVerifier::new("200a0e90e538d17390c8c4bc3bc71e44");
+  /// ```
+  pub fn new(secret: &str) -> Self {
+    MessageVerifier {
+      secret: secret.to_string(),
+      digest: Digest::default(),
+    }
+  }
+
+  /// Set the digest used to sign and verify messages.
+  ///
+  /// # Arguments
+  /// * `digest` - Digest to use for the HMAC signature
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::{Digest, MessageVerifier};
+  ///
+  /// let verifier =
+  ///   MessageVerifier::new("200a0e90e538d17390c8c4bc3bc71e44").with_digest(Digest::Sha256);
+  /// ```
+  pub fn with_digest(mut self, digest: Digest) -> Self {
+    self.digest = digest;
+
+    self
+  }
+
+  /// Serializes and signs `message`, returning the signed token.
+  ///
+  /// # Arguments
+  /// * `message` - Message to sign
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::MessageVerifier;
+  ///
+  /// let verifier = MessageVerifier::new("200a0e90e538d17390c8c4bc3bc71e44");
+  /// let token = verifier.generate("Peanut Butter Jelly Time").unwrap();
+  ///
+  /// assert_eq!(
+  ///   "BAhJIh1QZWFudXQgQnV0dGVyIEplbGx5IFRpbWUGOgZFVA==--a65ad7a2c4fe51e252ca5f975f11023f149eb322",
+  ///   token
+  /// );
+  /// ```
+  pub fn generate(&self, message: &str) -> anyhow::Result<String> {
+    let serialized_message = RubyMarshal::serialize(message)?;
+
+    self.sign_raw(&serialized_message)
+  }
+
+  /// Verifies the signature of a token and returns the deserialized message.
+  ///
+  /// # Arguments
+  /// * `signed_message` - Token to verify
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::MessageVerifier;
+  ///
+  /// let verifier = MessageVerifier::new("200a0e90e538d17390c8c4bc3bc71e44");
+  /// let token = "BAhJIh1QZWFudXQgQnV0dGVyIEplbGx5IFRpbWUGOgZFVA==--a65ad7a2c4fe51e252ca5f975f11023f149eb322";
+  ///
+  /// assert_eq!("Peanut Butter Jelly Time", verifier.verify(token).unwrap());
+  /// ```
+  pub fn verify(&self, signed_message: &str) -> anyhow::Result<String> {
+    let data = self.verify_raw(signed_message)?;
+    let content = RubyMarshal::deserialize(data)?;
+
+    Ok(String::from_utf8(content)?)
+  }
+
+  /// Checks whether a token carries a valid signature without deserializing it.
+  ///
+  /// # Arguments
+  /// * `signed_message` - Token to check
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::MessageVerifier;
+  ///
+  /// let verifier = MessageVerifier::new("200a0e90e538d17390c8c4bc3bc71e44");
+  ///
+  /// assert!(!verifier.valid_message("tampered--a65ad7a2c4fe51e252ca5f975f11023f149eb322"));
+  /// ```
+  pub fn valid_message(&self, signed_message: &str) -> bool {
+    self.verify_raw(signed_message).is_ok()
+  }
+
+  /// Signs `data` as is, without serializing it first.
+  ///
+  /// # Arguments
+  /// * `data` - Bytes to sign
+  ///
+  pub(crate) fn sign_raw(&self, data: &[u8]) -> anyhow::Result<String> {
+    let secret = hex::decode(&self.secret)?;
+    let data = general_purpose::STANDARD.encode(data);
+    let digest = hex::encode(self.digest.hmac(&secret, data.as_bytes()));
+
+    Ok(format!("{}--{}", data, digest))
+  }
+
+  /// Verifies the signature of a token and returns the signed bytes without
+  /// deserializing them.
+  ///
+  /// # Arguments
+  /// * `signed_message` - Token to verify
+  ///
+  pub(crate) fn verify_raw(&self, signed_message: &str) -> anyhow::Result<Vec<u8>> {
+    let (data, digest) = signed_message
+      .trim()
+      .rsplit_once("--")
+      .ok_or_else(|| anyhow!("Invalid signed contents"))?;
+    let secret = hex::decode(&self.secret)?;
+
+    if !self.digest.verify_hex(&secret, data.as_bytes(), digest) {
+      return Err(anyhow!("Signature verification not successful"));
+    }
+
+    Ok(general_purpose::STANDARD.decode(data)?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  static SECRET: &str = "200a0e90e538d17390c8c4bc3bc71e44";
+  static SHA256_TOKEN: &str = "BAhJIh1QZWFudXQgQnV0dGVyIEplbGx5IFRpbWUGOgZFVA==--49378d5e75cf63b47ee482a2482b6ec7aae532ae529bc18c75b74fe0b6950cc3";
+
+  #[test]
+  fn test_generate_verify_cycle() -> anyhow::Result<()> {
+    for digest in [Digest::Sha1, Digest::Sha256] {
+      let verifier = MessageVerifier::new(SECRET).with_digest(digest);
+      let token = verifier.generate("unsubscribe:42")?;
+
+      assert_eq!("unsubscribe:42", verifier.verify(&token)?);
+    }
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_verify_with_sha256_digest() -> anyhow::Result<()> {
+    let verifier = MessageVerifier::new(SECRET).with_digest(Digest::Sha256);
+
+    assert_eq!("Peanut Butter Jelly Time", verifier.verify(SHA256_TOKEN)?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_verify_fails_with_wrong_digest() {
+    let verifier = MessageVerifier::new(SECRET);
+
+    assert!(verifier.verify(SHA256_TOKEN).is_err());
+  }
+
+  #[test]
+  fn test_verify_fails_with_wrong_secret() {
+    let verifier =
+      MessageVerifier::new("94b6b40cabf62ee59c9aa13a86f0e7d7").with_digest(Digest::Sha256);
+
+    assert!(!verifier.valid_message(SHA256_TOKEN));
+  }
+
+  #[test]
+  fn test_verify_fails_without_signature() {
+    let verifier = MessageVerifier::new(SECRET);
+
+    assert!(
+      verifier
+        .verify("BAhJIh1QZWFudXQgQnV0dGVyIEplbGx5IFRpbWUGOgZFVA==")
+        .is_err()
+    );
+  }
+}
@@ -0,0 +1,174 @@
+use anyhow::anyhow;
+use base64::{Engine as _, engine::general_purpose};
+use serde_json::{Map, Value};
+use std::time::SystemTime;
+
+/// Collection of functions that wrap/unwrap serialized messages in the
+/// `{"_rails": {"message": ..., "exp": ..., "pur": ...}}` envelope Rails uses
+/// to bind a message to a purpose and an expiry.
+pub(crate) struct Metadata {}
+
+impl Metadata {
+  /// Wraps an already serialized message in the metadata envelope. When neither a
+  /// purpose nor an expiry is given, the message is returned untouched, like Rails.
+  ///
+  /// # Arguments
+  /// * `message` - Serialized message to wrap
+  /// * `purpose` - Purpose the message is valid for
+  /// * `expires_at` - Point in time after which the message is no longer valid
+  ///
+  pub(crate) fn wrap(
+    message: &[u8],
+    purpose: Option<&str>,
+    expires_at: Option<SystemTime>,
+  ) -> Vec<u8> {
+    if purpose.is_none() && expires_at.is_none() {
+      return message.to_vec();
+    }
+
+    let mut metadata = Map::new();
+
+    metadata.insert(
+      "message".to_string(),
+      Value::from(general_purpose::STANDARD.encode(message)),
+    );
+    metadata.insert(
+      "exp".to_string(),
+      expires_at.map_or(Value::Null, |expires_at| {
+        Value::from(humantime::format_rfc3339_millis(expires_at).to_string())
+      }),
+    );
+    metadata.insert("pur".to_string(), purpose.map_or(Value::Null, Value::from));
+
+    let mut envelope = Map::new();
+
+    envelope.insert("_rails".to_string(), Value::Object(metadata));
+
+    Value::Object(envelope).to_string().into_bytes()
+  }
+
+  /// Checks the purpose and expiry of a message wrapped in the metadata envelope
+  /// and returns the serialized message inside it. Messages without an envelope are
+  /// only accepted when no purpose is expected.
+  ///
+  /// Both the base64 `message` form and the inline `data` form used by Rails 7.1
+  /// are understood.
+  ///
+  /// # Arguments
+  /// * `contents` - Decrypted contents, possibly wrapped in the envelope
+  /// * `purpose` - Purpose the message is expected to have
+  ///
+  pub(crate) fn verify(contents: &[u8], purpose: Option<&str>) -> anyhow::Result<Vec<u8>> {
+    let Some(metadata) = Self::extract(contents) else {
+      return match purpose {
+        None => Ok(contents.to_vec()),
+        Some(_) => Err(anyhow!("Message purpose does not match")),
+      };
+    };
+
+    if metadata.get("pur").and_then(Value::as_str) != purpose {
+      return Err(anyhow!("Message purpose does not match"));
+    }
+
+    if let Some(expires_at) = metadata.get("exp").and_then(Value::as_str) {
+      let expires_at = humantime::parse_rfc3339_weak(expires_at)?;
+
+      if SystemTime::now() >= expires_at {
+        return Err(anyhow!("Message has expired"));
+      }
+    }
+
+    match (metadata.get("message"), metadata.get("data")) {
+      (Some(Value::String(message)), _) => Ok(general_purpose::STANDARD.decode(message)?),
+      (_, Some(data)) => Ok(serde_json::to_vec(data)?),
+      _ => Err(anyhow!("Invalid message metadata")),
+    }
+  }
+
+  fn extract(contents: &[u8]) -> Option<Map<String, Value>> {
+    if contents.first() != Some(&b'{') {
+      return None;
+    }
+
+    match serde_json::from_slice::<Value>(contents) {
+      Ok(Value::Object(mut envelope)) => match envelope.remove("_rails") {
+        Some(Value::Object(metadata)) => Some(metadata),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  #[test]
+  fn test_wrap_without_metadata() {
+    let wrapped = Metadata::wrap(b"message", None, None);
+
+    assert_eq!(b"message", wrapped.as_slice());
+  }
+
+  #[test]
+  fn test_wrap_with_purpose_and_expiry() {
+    let expires_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+    let wrapped = Metadata::wrap(b"message", Some("login"), Some(expires_at));
+
+    assert_eq!(
+      r#"{"_rails":{"exp":"2027-01-15T08:00:00.000Z","message":"bWVzc2FnZQ==","pur":"login"}}"#,
+      String::from_utf8(wrapped).unwrap()
+    );
+  }
+
+  #[test]
+  fn test_verify_round_trip() -> anyhow::Result<()> {
+    let expires_at = SystemTime::now() + Duration::from_secs(60);
+    let wrapped = Metadata::wrap(b"message", Some("login"), Some(expires_at));
+
+    assert_eq!(
+      b"message",
+      Metadata::verify(&wrapped, Some("login"))?.as_slice()
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_verify_rejects_wrong_purpose() {
+    let wrapped = Metadata::wrap(b"message", Some("login"), None);
+
+    assert!(Metadata::verify(&wrapped, Some("shipping")).is_err());
+    assert!(Metadata::verify(&wrapped, None).is_err());
+  }
+
+  #[test]
+  fn test_verify_rejects_expired_message() {
+    let expires_at = SystemTime::now() - Duration::from_secs(60);
+    let wrapped = Metadata::wrap(b"message", None, Some(expires_at));
+
+    assert!(Metadata::verify(&wrapped, None).is_err());
+  }
+
+  #[test]
+  fn test_verify_without_envelope() {
+    assert_eq!(
+      b"message",
+      Metadata::verify(b"message", None).unwrap().as_slice()
+    );
+    assert!(Metadata::verify(b"message", Some("login")).is_err());
+  }
+
+  #[test]
+  fn test_verify_inline_data() -> anyhow::Result<()> {
+    let contents = br#"{"_rails":{"data":{"user_id":1},"pur":"cookie._session"}}"#;
+
+    let data = Metadata::verify(contents, Some("cookie._session"))?;
+
+    assert_eq!(br#"{"user_id":1}"#, data.as_slice());
+
+    Ok(())
+  }
+}
@@ -0,0 +1,278 @@
+use crate::encryption::Digest;
+use crate::encryption::metadata::Metadata;
+use crate::{KeyGenerator, MessageEncryption};
+use anyhow::anyhow;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use serde_json::Value;
+use std::time::SystemTime;
+
+static EMPTY_AAD_STRING: &str = "";
+
+/// Characters left unescaped in cookie values, matching `URI.encode_www_form_component`.
+const COOKIE_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+  .remove(b'*')
+  .remove(b'-')
+  .remove(b'.')
+  .remove(b'_');
+
+/// Contents of an encrypted Rails cookie.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CookiePayload {
+  /// Payload written by the `:json` (or `:hybrid`) cookie serializer.
+  Json(Value),
+  /// Payload written by the `:marshal` cookie serializer, as raw Ruby Marshal bytes.
+  Marshal(Vec<u8>),
+}
+
+/// Reads and writes encrypted cookies produced by Rails 5.2 and newer, which are
+/// encrypted with AES-256-GCM using a key derived from `secret_key_base`.
+///
+/// # Examples
+///
+/// ```
+/// use street_cred::{CookiePayload, RailsCookie};
+///
+/// let cookies = RailsCookie::new("secret_key_base");
+/// let value = "SROV8lq5plXeCPYaO1A48VFJijWZIWKCg9Y1T9QK3bGD%2B1UGAaXUYwmBsMvWxqCM7D74CD7cbpIAzvObI%2FA%2Bt4JLp85bYmubFL8TgoQvGMGkE3Gn4t9Ao2%2FvIQ%2B0w6SgPaxYX38rueIejf4whmkmXg%3D%3D--AAECAwQFBgcICQoL--tlWAXCQ%2Blq8iQVAIqM6ZYg%3D%3D";
+///
+/// let session = cookies.decode("_app_session", value).unwrap();
+///
+/// assert_eq!(
+///   CookiePayload::Json(serde_json::json!({"session_id": "abc123", "user_id": 42})),
+///   session
+/// );
+/// ```
+pub struct RailsCookie {
+  secret_key_base: String,
+  salt: String,
+  digest: Digest,
+}
+
+impl RailsCookie {
+  /// Salt Rails uses by default to derive the encrypted cookie key.
+  pub const DEFAULT_SALT: &'static str = "authenticated encrypted cookie";
+
+  /// Create a new instance of RailsCookie. The salt defaults to
+  /// "authenticated encrypted cookie" and the key derivation digest defaults
+  /// to SHA1, matching Rails before 7.0.
+  ///
+  /// # Arguments
+  /// * `secret_key_base` - The application's `secret_key_base`
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::RailsCookie;
+  ///
+  /// let cookies = RailsCookie::new("secret_key_base");
+  /// ```
+  pub fn new(secret_key_base: &str) -> Self {
+    RailsCookie {
+      secret_key_base: secret_key_base.to_string(),
+      salt: Self::DEFAULT_SALT.to_string(),
+      digest: Digest::default(),
+    }
+  }
+
+  /// Set the salt used to derive the cookie key, i.e.
+  /// `config.action_dispatch.authenticated_encrypted_cookie_salt`.
+  ///
+  /// # Arguments
+  /// * `salt` - Salt used for key derivation
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::RailsCookie;
+  ///
+  /// let cookies = RailsCookie::new("secret_key_base").with_salt("custom salt");
+  /// ```
+  pub fn with_salt(mut self, salt: &str) -> Self {
+    self.salt = salt.to_string();
+
+    self
+  }
+
+  /// Set the digest used to derive the cookie key, i.e.
+  /// `config.active_support.key_generator_hash_digest_class`. Applications on
+  /// Rails 7.0 defaults use SHA256.
+  ///
+  /// # Arguments
+  /// * `digest` - Digest used for key derivation
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::{Digest, RailsCookie};
+  ///
+  /// let cookies = RailsCookie::new("secret_key_base").with_digest(Digest::Sha256);
+  /// ```
+  pub fn with_digest(mut self, digest: Digest) -> Self {
+    self.digest = digest;
+
+    self
+  }
+
+  /// Decrypts a cookie value, checking it was issued for the cookie `name` and
+  /// has not expired, and returns its payload.
+  ///
+  /// # Arguments
+  /// * `name` - Name of the cookie, used to check the `cookie.<name>` purpose
+  /// * `value` - URL encoded cookie value
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::RailsCookie;
+  ///
+  /// let cookies = RailsCookie::new("secret_key_base");
+  /// let result = cookies.decode("_app_session", "not--a--cookie");
+  ///
+  /// assert!(result.is_err());
+  /// ```
+  pub fn decode(&self, name: &str, value: &str) -> anyhow::Result<CookiePayload> {
+    let value = percent_decode_str(value).decode_utf8()?;
+    let split_contents = MessageEncryption::split_encrypted_contents(&value)?;
+
+    let decryptor = MessageEncryption::new(
+      split_contents[0].as_bytes().to_vec(),
+      &self.key(),
+      EMPTY_AAD_STRING,
+    );
+    let decrypted = decryptor.decrypt_raw(split_contents[1], split_contents[2])?;
+    let serialized = Metadata::verify(&decrypted, Some(&Self::purpose(name)))?;
+
+    if serialized.starts_with(b"\x04\x08") {
+      Ok(CookiePayload::Marshal(serialized))
+    } else {
+      Ok(CookiePayload::Json(serde_json::from_slice(&serialized)?))
+    }
+  }
+
+  /// Encrypts a payload for the cookie `name` and returns the URL encoded
+  /// cookie value.
+  ///
+  /// # Arguments
+  /// * `name` - Name of the cookie, used for the `cookie.<name>` purpose
+  /// * `payload` - Payload to store in the cookie
+  /// * `expires_at` - Point in time after which the cookie is no longer valid
+  ///
+  /// # Examples
+  /// ```
+  /// use street_cred::{CookiePayload, RailsCookie};
+  ///
+  /// let cookies = RailsCookie::new("secret_key_base");
+  /// let payload = CookiePayload::Json(serde_json::json!({"user_id": 42}));
+  ///
+  /// let value = cookies.encode("_app_session", &payload, None).unwrap();
+  ///
+  /// assert_eq!(payload, cookies.decode("_app_session", &value).unwrap());
+  /// ```
+  pub fn encode(
+    &self,
+    name: &str,
+    payload: &CookiePayload,
+    expires_at: Option<SystemTime>,
+  ) -> anyhow::Result<String> {
+    let serialized = match payload {
+      CookiePayload::Json(value) => serde_json::to_vec(value)?,
+      CookiePayload::Marshal(data) if data.starts_with(b"\x04\x08") => data.clone(),
+      CookiePayload::Marshal(_) => return Err(anyhow!("Invalid Marshal payload")),
+    };
+    let wrapped = Metadata::wrap(&serialized, Some(&Self::purpose(name)), expires_at);
+
+    let encryptor = MessageEncryption::new(Vec::new(), &self.key(), EMPTY_AAD_STRING);
+    let encrypted = encryptor.encrypt_raw(&wrapped)?;
+
+    Ok(utf8_percent_encode(&encrypted, COOKIE_VALUE).to_string())
+  }
+
+  fn key(&self) -> String {
+    KeyGenerator::new(&self.secret_key_base)
+      .with_iterations(1000)
+      .with_digest(self.digest)
+      .generate_key(&self.salt, 32)
+  }
+
+  fn purpose(name: &str) -> String {
+    format!("cookie.{}", name)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use std::time::Duration;
+
+  static MESSAGE_COOKIE: &str = "SROV8lq5plXeCPYaO1A48VFJijWZIWKCg9Y1T9QK3bGD%2B1UGAaXUYwmBsMvWxqCM7D74CD7cbpIAzvObI%2FA%2Bt4JLp85bYmubFL8TgoQvGMGkE3Gn4t9Ao2%2FvIQ%2B0w6SgPaxYX38rueIejf4whmkmXg%3D%3D--AAECAwQFBgcICQoL--tlWAXCQ%2Blq8iQVAIqM6ZYg%3D%3D";
+  static DATA_COOKIE: &str = "SROV8lq5plXeCPYaMlQ%2F4xIUlDXQZnSIoMMBSPMUntyY729XXPyuK0rKnO%2F53rKxwWaLVXvIJugr2uDVIO4RmKdRy%2FpkKWuNFLkEnc18E5qXHi31zdlX8iakLQ751bA%3D--AAECAwQFBgcICQoL--aWnTTk3J89ZAcBELHavS6g%3D%3D";
+  static EXPIRED_COOKIE: &str = "SROV8lq5plXeCPYaO1A48VFJijWZIWKCg9Y1T9QK3bGD%2B1UGAaXUYwmBsMvWxqCM7D74CD7cbpIAzvObI%2FA%2Bt4JLp85bYmubFL8TgoQvVIb4T2yoopsfsWSZclDhmP3%2FI8MXHz9EkKVB3OcqmmlhAf%2BFKQjPa2ZGupSXklaR96faqi%2BScA0%3D--AAECAwQFBgcICQoL--%2FO2oTI6%2BvELOnr0Yxr8u8w%3D%3D";
+
+  #[test]
+  fn test_decode_message_cookie() -> anyhow::Result<()> {
+    let cookies = RailsCookie::new("secret_key_base");
+
+    let payload = cookies.decode("_app_session", MESSAGE_COOKIE)?;
+
+    assert_eq!(
+      CookiePayload::Json(json!({"session_id": "abc123", "user_id": 42})),
+      payload
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_decode_data_cookie() -> anyhow::Result<()> {
+    let cookies = RailsCookie::new("secret_key_base");
+
+    let payload = cookies.decode("_app_session", DATA_COOKIE)?;
+
+    assert_eq!(
+      CookiePayload::Json(json!({"session_id": "abc123", "user_id": 42})),
+      payload
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_decode_rejects_other_cookie_name() {
+    let cookies = RailsCookie::new("secret_key_base");
+
+    assert!(cookies.decode("remember_token", MESSAGE_COOKIE).is_err());
+  }
+
+  #[test]
+  fn test_decode_rejects_expired_cookie() {
+    let cookies = RailsCookie::new("secret_key_base");
+
+    assert!(cookies.decode("_app_session", EXPIRED_COOKIE).is_err());
+  }
+
+  #[test]
+  fn test_decode_rejects_wrong_secret() {
+    let cookies = RailsCookie::new("another secret_key_base");
+
+    assert!(cookies.decode("_app_session", MESSAGE_COOKIE).is_err());
+  }
+
+  #[test]
+  fn test_encode_decode_marshal_payload() -> anyhow::Result<()> {
+    let cookies = RailsCookie::new("secret_key_base").with_digest(Digest::Sha256);
+    let payload = CookiePayload::Marshal(b"\x04\x08i\x2f".to_vec());
+    let expires_at = SystemTime::now() + Duration::from_secs(60);
+
+    let value = cookies.encode("remember_token", &payload, Some(expires_at))?;
+
+    assert!(!value.contains('='));
+    assert_eq!(payload, cookies.decode("remember_token", &value)?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_encode_rejects_invalid_marshal_payload() {
+    let cookies = RailsCookie::new("secret_key_base");
+    let payload = CookiePayload::Marshal(b"not marshal".to_vec());
+
+    assert!(cookies.encode("remember_token", &payload, None).is_err());
+  }
+}
@@ -0,0 +1,543 @@
+use crate::CipherGeneration;
+use crate::encryption::Cipher;
+use anyhow::anyhow;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::io::{self, Read, Write};
+
+/// Magic bytes starting every stream, followed by the format version.
+static MAGIC: &[u8] = b"SCS1";
+/// Size of the plaintext of every chunk but the last one.
+pub(crate) const CHUNK_SIZE: usize = 64 * 1024;
+/// Size of the authentication tag appended to every chunk.
+const TAG_SIZE: usize = 16;
+/// Bytes of the nonce taken by the chunk counter and the final chunk flag.
+const NONCE_SUFFIX_SIZE: usize = 5;
+/// Size of the random salt every stream derives its key from.
+const SALT_SIZE: usize = 32;
+
+/// Encrypts a stream of data in chunks, so files of any size can be encrypted
+/// without holding them in memory. This uses the STREAM construction: every
+/// chunk is encrypted separately with a nonce made of a random prefix, the
+/// chunk's index and a flag marking the final chunk, so chunks can't be
+/// reordered, dropped or truncated without decryption failing.
+///
+/// Chunks aren't encrypted with the key itself, but with a key derived from it
+/// and a random salt using HKDF-SHA256, like Tink's streaming AEAD. Every stream
+/// gets a fresh key, so nonces can't collide across streams.
+///
+/// The output is binary: a header ("SCS1", the cipher, the salt and the nonce
+/// prefix) followed by the encrypted chunks of 64 KiB. Call [StreamEncryptor::finish]
+/// once all data is written, to write the final chunk.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Read, Write};
+/// use street_cred::{StreamDecryptor, StreamEncryptor};
+///
+/// let key = "200a0e90e538d17390c8c4bc3bc71e44";
+/// let mut encryptor = StreamEncryptor::new(Vec::new(), key);
+///
+/// encryptor.write_all(b"a large database dump").unwrap();
+///
+/// let encrypted = encryptor.finish().unwrap();
+/// let mut decrypted = String::new();
+///
+/// StreamDecryptor::new(encrypted.as_slice(), key)
+///   .read_to_string(&mut decrypted)
+///   .unwrap();
+///
+/// assert_eq!("a large database dump", decrypted);
+/// ```
+pub struct StreamEncryptor<W: Write> {
+  inner: W,
+  key: String,
+  cipher: Option<Cipher>,
+  stream: Option<StreamState>,
+  buffer: Vec<u8>,
+}
+
+impl<W: Write> StreamEncryptor<W> {
+  /// Create a new StreamEncryptor writing the encrypted stream to `inner`.
+  ///
+  /// # Arguments
+  /// * `inner` - Writer receiving the encrypted stream
+  /// * `key` - Hex encoded key. Its length selects between AES-128-GCM and
+  ///   AES-256-GCM, unless a cipher is set with [StreamEncryptor::with_cipher].
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::StreamEncryptor;
+  ///
+  /// let encryptor = StreamEncryptor::new(std::io::stdout(), "200a0e90e538d17390c8c4bc3bc71e44");
+  /// ```
+  pub fn new(inner: W, key: &str) -> Self {
+    StreamEncryptor {
+      inner,
+      key: key.trim().to_string(),
+      cipher: None,
+      stream: None,
+      buffer: Vec::with_capacity(CHUNK_SIZE),
+    }
+  }
+
+  /// Set the cipher to encrypt with. It is recorded in the stream's header, so
+  /// decrypting picks it automatically.
+  ///
+  /// # Arguments
+  /// * `cipher` - Cipher to encrypt with. Its key length must match the key's.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::{Cipher, CipherGeneration, StreamEncryptor};
+  ///
+  /// let key = CipherGeneration::random_key_for(Cipher::XChaCha20Poly1305);
+  /// let encryptor = StreamEncryptor::new(Vec::new(), &key).with_cipher(Cipher::XChaCha20Poly1305);
+  /// ```
+  pub fn with_cipher(mut self, cipher: Cipher) -> Self {
+    self.cipher = Some(cipher);
+
+    self
+  }
+
+  /// Encrypts the remaining data as the final chunk, flushes the writer and
+  /// returns it. Streams that aren't finished can't be decrypted.
+  pub fn finish(mut self) -> io::Result<W> {
+    self.start()?;
+
+    let chunk = std::mem::take(&mut self.buffer);
+
+    self.write_chunk(&chunk, true)?;
+    self.inner.flush()?;
+
+    Ok(self.inner)
+  }
+
+  /// Writes the header, on the first write.
+  fn start(&mut self) -> io::Result<()> {
+    if self.stream.is_some() {
+      return Ok(());
+    }
+
+    let key = hex::decode(&self.key).map_err(invalid_input)?;
+    let cipher = match self.cipher {
+      Some(cipher) => cipher,
+      None => Cipher::for_key(&key).map_err(invalid_input)?,
+    };
+
+    if key.len() != cipher.key_len() {
+      return Err(invalid_input(anyhow!("Invalid key length")));
+    }
+
+    let salt = CipherGeneration::random_bytes(SALT_SIZE);
+    let prefix = CipherGeneration::random_bytes(cipher.nonce_len() - NONCE_SUFFIX_SIZE);
+    let stream = StreamState::new(cipher, &key, salt, prefix)?;
+
+    self.inner.write_all(&stream.header)?;
+    self.stream = Some(stream);
+
+    Ok(())
+  }
+
+  fn write_chunk(&mut self, chunk: &[u8], last: bool) -> io::Result<()> {
+    let stream = self
+      .stream
+      .as_mut()
+      .ok_or_else(|| invalid_input(anyhow!("Stream not started")))?;
+    let nonce = stream.next_nonce(last)?;
+    let encrypted = stream
+      .cipher
+      .encrypt(&stream.key, &nonce, chunk, &stream.header)
+      .map_err(invalid_input)?;
+
+    self.inner.write_all(&encrypted)
+  }
+}
+
+impl<W: Write> Write for StreamEncryptor<W> {
+  fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+    self.start()?;
+
+    let mut rest = data;
+
+    // A full chunk is only written once more data follows it, so the final
+    // chunk written by finish is never empty unless the whole stream is.
+    while !rest.is_empty() {
+      if self.buffer.len() == CHUNK_SIZE {
+        let mut chunk = std::mem::take(&mut self.buffer);
+
+        self.write_chunk(&chunk, false)?;
+        chunk.clear();
+        self.buffer = chunk;
+      }
+
+      let length = rest.len().min(CHUNK_SIZE - self.buffer.len());
+
+      self.buffer.extend_from_slice(&rest[..length]);
+      rest = &rest[length..];
+    }
+
+    Ok(data.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
+
+/// Decrypts a stream encrypted by [StreamEncryptor], chunk by chunk. Reading
+/// fails as soon as a chunk was tampered with, reordered or is missing, so
+/// data read before an error must not be trusted on its own.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Read;
+/// use street_cred::StreamDecryptor;
+///
+/// let mut decryptor = StreamDecryptor::new(std::io::stdin(), "200a0e90e538d17390c8c4bc3bc71e44");
+/// ```
+pub struct StreamDecryptor<R: Read> {
+  inner: R,
+  key: String,
+  stream: Option<StreamState>,
+  pending: Vec<u8>,
+  plaintext: Vec<u8>,
+  position: usize,
+  finished: bool,
+}
+
+impl<R: Read> StreamDecryptor<R> {
+  /// Create a new StreamDecryptor reading an encrypted stream from `inner`.
+  ///
+  /// # Arguments
+  /// * `inner` - Reader providing the encrypted stream
+  /// * `key` - Hex encoded key the stream was encrypted with
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::StreamDecryptor;
+  ///
+  /// let decryptor = StreamDecryptor::new(std::io::stdin(), "200a0e90e538d17390c8c4bc3bc71e44");
+  /// ```
+  pub fn new(inner: R, key: &str) -> Self {
+    StreamDecryptor {
+      inner,
+      key: key.trim().to_string(),
+      stream: None,
+      pending: Vec::new(),
+      plaintext: Vec::new(),
+      position: 0,
+      finished: false,
+    }
+  }
+
+  /// Reads and checks the header, on the first read.
+  fn start(&mut self) -> io::Result<()> {
+    if self.stream.is_some() {
+      return Ok(());
+    }
+
+    let mut magic = [0; 5];
+
+    self.inner.read_exact(&mut magic).map_err(truncated)?;
+
+    if &magic[..4] != MAGIC {
+      return Err(invalid_data(anyhow!("Not an encrypted stream")));
+    }
+
+    let mut cipher_name = vec![0; magic[4] as usize];
+
+    self.inner.read_exact(&mut cipher_name).map_err(truncated)?;
+
+    let cipher: Cipher = String::from_utf8_lossy(&cipher_name)
+      .parse()
+      .map_err(invalid_data)?;
+    let mut salt = vec![0; SALT_SIZE];
+    let mut prefix = vec![0; cipher.nonce_len() - NONCE_SUFFIX_SIZE];
+
+    self.inner.read_exact(&mut salt).map_err(truncated)?;
+    self.inner.read_exact(&mut prefix).map_err(truncated)?;
+
+    let key = hex::decode(&self.key).map_err(invalid_input)?;
+
+    self.stream = Some(StreamState::new(cipher, &key, salt, prefix)?);
+
+    Ok(())
+  }
+
+  /// Decrypts the next chunk into the plaintext buffer. A chunk is the final one
+  /// when no data follows it, which its nonce has to confirm.
+  fn read_chunk(&mut self) -> io::Result<()> {
+    let full_chunk = CHUNK_SIZE + TAG_SIZE;
+
+    while self.pending.len() <= full_chunk {
+      let mut buffer = vec![0; full_chunk + 1 - self.pending.len()];
+      let read = self.inner.read(&mut buffer)?;
+
+      if read == 0 {
+        break;
+      }
+
+      self.pending.extend_from_slice(&buffer[..read]);
+    }
+
+    let last = self.pending.len() <= full_chunk;
+    let rest = self.pending.split_off(self.pending.len().min(full_chunk));
+    let chunk = std::mem::replace(&mut self.pending, rest);
+
+    if chunk.len() < TAG_SIZE {
+      return Err(truncated(io::ErrorKind::UnexpectedEof.into()));
+    }
+
+    let stream = self
+      .stream
+      .as_mut()
+      .ok_or_else(|| invalid_input(anyhow!("Stream not started")))?;
+    let nonce = stream.next_nonce(last)?;
+
+    self.plaintext = stream
+      .cipher
+      .decrypt(&stream.key, &nonce, &chunk, &stream.header)
+      .map_err(|_| {
+        invalid_data(anyhow!(
+          "Invalid encrypted stream: wrong key, or the stream was tampered with or truncated"
+        ))
+      })?;
+    self.position = 0;
+    self.finished = last;
+
+    Ok(())
+  }
+}
+
+impl<R: Read> Read for StreamDecryptor<R> {
+  fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+    self.start()?;
+
+    while self.position == self.plaintext.len() {
+      if self.finished {
+        return Ok(0);
+      }
+
+      self.read_chunk()?;
+    }
+
+    let length = buffer.len().min(self.plaintext.len() - self.position);
+
+    buffer[..length].copy_from_slice(&self.plaintext[self.position..self.position + length]);
+    self.position += length;
+
+    Ok(length)
+  }
+}
+
+/// Cipher, key and nonce state shared by both ends of a stream.
+struct StreamState {
+  cipher: Cipher,
+  key: Vec<u8>,
+  header: Vec<u8>,
+  prefix: Vec<u8>,
+  counter: Option<u32>,
+}
+
+impl StreamState {
+  /// Builds the header and derives the stream's key from `master_key` and
+  /// `salt`. The magic and cipher are the HKDF info, binding the key to them.
+  fn new(cipher: Cipher, master_key: &[u8], salt: Vec<u8>, prefix: Vec<u8>) -> io::Result<Self> {
+    let cipher_name = cipher.to_string();
+    let mut header = MAGIC.to_vec();
+
+    header.push(cipher_name.len() as u8);
+    header.extend_from_slice(cipher_name.as_bytes());
+
+    let mut key = vec![0; cipher.key_len()];
+
+    Hkdf::<Sha256>::new(Some(&salt), master_key)
+      .expand(&header, &mut key)
+      .map_err(|_| invalid_input(anyhow!("Invalid key length")))?;
+
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(&prefix);
+
+    Ok(StreamState {
+      cipher,
+      key,
+      header,
+      prefix,
+      counter: Some(0),
+    })
+  }
+
+  /// Returns the nonce of the next chunk: prefix, big endian counter and flag.
+  fn next_nonce(&mut self, last: bool) -> io::Result<Vec<u8>> {
+    let counter = self
+      .counter
+      .ok_or_else(|| invalid_input(anyhow!("Stream is too long")))?;
+    let mut nonce = self.prefix.clone();
+
+    nonce.extend_from_slice(&counter.to_be_bytes());
+    nonce.push(last as u8);
+    self.counter = counter.checked_add(1);
+
+    Ok(nonce)
+  }
+}
+
+fn invalid_input(why: impl Into<anyhow::Error>) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidInput, why.into().to_string())
+}
+
+fn invalid_data(why: impl Into<anyhow::Error>) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, why.into().to_string())
+}
+
+fn truncated(why: io::Error) -> io::Error {
+  match why.kind() {
+    io::ErrorKind::UnexpectedEof => invalid_data(anyhow!("Encrypted stream is truncated")),
+    _ => why,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn encrypt(key: &str, cipher: Option<Cipher>, data: &[u8]) -> Vec<u8> {
+    let mut encryptor = StreamEncryptor::new(Vec::new(), key);
+
+    if let Some(cipher) = cipher {
+      encryptor = encryptor.with_cipher(cipher);
+    }
+
+    // Uneven writes, to cross chunk boundaries mid-write.
+    for part in data.chunks(10_000) {
+      encryptor.write_all(part).unwrap();
+    }
+
+    encryptor.finish().unwrap()
+  }
+
+  fn decrypt(key: &str, encrypted: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decrypted = Vec::new();
+
+    StreamDecryptor::new(encrypted, key).read_to_end(&mut decrypted)?;
+
+    Ok(decrypted)
+  }
+
+  fn data(length: usize) -> Vec<u8> {
+    (0..length).map(|index| (index % 251) as u8).collect()
+  }
+
+  #[test]
+  fn test_encrypt_decrypt_cycle_around_chunk_boundaries() {
+    let key = CipherGeneration::random_key();
+
+    for length in [
+      0,
+      1,
+      CHUNK_SIZE - 1,
+      CHUNK_SIZE,
+      CHUNK_SIZE + 1,
+      3 * CHUNK_SIZE,
+    ] {
+      let data = data(length);
+      let encrypted = encrypt(&key, None, &data);
+      let chunks = length.div_ceil(CHUNK_SIZE).max(1);
+
+      assert_eq!(
+        5 + "aes-128-gcm".len() + SALT_SIZE + 7 + length + chunks * TAG_SIZE,
+        encrypted.len()
+      );
+      assert_eq!(data, decrypt(&key, &encrypted).unwrap());
+    }
+  }
+
+  #[test]
+  fn test_encrypt_decrypt_cycle_with_ciphers() {
+    for cipher in [
+      Cipher::Aes128Gcm,
+      Cipher::Aes256Gcm,
+      Cipher::XChaCha20Poly1305,
+      Cipher::Aes256GcmSiv,
+    ] {
+      let key = CipherGeneration::random_key_for(cipher);
+      let data = data(CHUNK_SIZE + 100);
+
+      assert_eq!(
+        data,
+        decrypt(&key, &encrypt(&key, Some(cipher), &data)).unwrap()
+      );
+    }
+  }
+
+  #[test]
+  fn test_decrypt_rejects_truncated_streams() {
+    let key = CipherGeneration::random_key();
+    let encrypted = encrypt(&key, None, &data(2 * CHUNK_SIZE + 10));
+    let header_size = 5 + "aes-128-gcm".len() + SALT_SIZE + 7;
+
+    // Cut after the first chunk, which was encrypted as a non-final chunk.
+    let truncated = &encrypted[..header_size + CHUNK_SIZE + TAG_SIZE];
+
+    assert!(decrypt(&key, truncated).is_err());
+    assert!(decrypt(&key, &encrypted[..encrypted.len() - 1]).is_err());
+    assert!(decrypt(&key, &encrypted[..header_size]).is_err());
+    assert!(decrypt(&key, &encrypted[..3]).is_err());
+  }
+
+  #[test]
+  fn test_decrypt_rejects_tampering_and_wrong_keys() {
+    let key = CipherGeneration::random_key();
+    let mut encrypted = encrypt(&key, None, &data(100));
+
+    assert!(decrypt(&CipherGeneration::random_key(), &encrypted).is_err());
+
+    let last = encrypted.len() - 1;
+
+    encrypted[last] ^= 1;
+
+    assert!(decrypt(&key, &encrypted).is_err());
+  }
+
+  #[test]
+  fn test_streams_use_derived_keys() {
+    let key = CipherGeneration::random_key();
+    let data = data(100);
+    let first = encrypt(&key, None, &data);
+    let second = encrypt(&key, None, &data);
+    let header_size = 5 + "aes-128-gcm".len() + SALT_SIZE + 7;
+
+    assert_ne!(first[..header_size], second[..header_size]);
+    assert_ne!(first[header_size..], second[header_size..]);
+
+    // The chunk doesn't decrypt with the master key itself.
+    let mut nonce = first[header_size - 7..header_size].to_vec();
+
+    nonce.extend_from_slice(&[0, 0, 0, 0, 1]);
+
+    assert!(
+      Cipher::Aes128Gcm
+        .decrypt(
+          &hex::decode(&key).unwrap(),
+          &nonce,
+          &first[header_size..],
+          &first[..header_size]
+        )
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn test_encrypt_rejects_mismatched_cipher() {
+    let key = CipherGeneration::random_key();
+    let mut encryptor = StreamEncryptor::new(Vec::new(), &key).with_cipher(Cipher::Aes256Gcm);
+
+    assert!(encryptor.write_all(b"data").is_err());
+  }
+}
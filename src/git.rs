@@ -0,0 +1,11 @@
+mod git_filter;
+mod git_merge;
+mod git_revision;
+mod git_scan;
+mod git_setup;
+
+pub use self::git_filter::GitFilter;
+pub use self::git_merge::GitMerge;
+pub use self::git_revision::GitRevision;
+pub use self::git_scan::{GitScan, ScanFinding};
+pub use self::git_setup::GitSetup;
@@ -0,0 +1,138 @@
+use crate::FileEncryption;
+
+/// Collection of functions that encrypt plaintext working files for git, as a
+/// clean/smudge filter: the working tree holds the plaintext, while git only
+/// ever stores the encrypted contents.
+pub struct GitFilter {}
+
+impl GitFilter {
+  /// Encrypts the plaintext of a working file before git stores it. Encryption
+  /// is deterministic, so unchanged files encrypt to the same contents and
+  /// `git status` stays clean. Contents that the key already decrypts are
+  /// returned unchanged, so files committed encrypted before the filter was set
+  /// up aren't encrypted twice.
+  ///
+  /// # Arguments
+  /// * `file_path` - Path of the working file, as given by git's `%f`
+  /// * `key` - Key to encrypt with
+  /// * `contents` - Plaintext contents of the working file
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::GitFilter;
+  ///
+  /// let key = "200a0e90e538d17390c8c4bc3bc71e44";
+  /// let first = GitFilter::clean("secrets.yml", key, b"api_key: 123\n").unwrap();
+  /// let second = GitFilter::clean("secrets.yml", key, b"api_key: 123\n").unwrap();
+  ///
+  /// assert_eq!(first, second);
+  /// ```
+  pub fn clean(file_path: &str, key: &str, contents: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let file_encryption =
+      FileEncryption::new(file_path.to_string(), key.to_string()).deterministic();
+
+    if let Ok(encrypted_contents) = std::str::from_utf8(contents)
+      && file_encryption.decrypt_contents(encrypted_contents).is_ok()
+    {
+      return Ok(contents.to_vec());
+    }
+
+    Ok(file_encryption.encrypt(contents)?.into_bytes())
+  }
+
+  /// Decrypts the contents git stores for a file, when checking it out into the
+  /// working tree.
+  ///
+  /// # Arguments
+  /// * `file_path` - Path of the working file, as given by git's `%f`
+  /// * `key` - Key to decrypt with
+  /// * `contents` - Encrypted contents stored by git
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::GitFilter;
+  ///
+  /// let key = "200a0e90e538d17390c8c4bc3bc71e44";
+  /// let encrypted = GitFilter::clean("secrets.yml", key, b"api_key: 123\n").unwrap();
+  ///
+  /// assert_eq!(b"api_key: 123\n".to_vec(), GitFilter::smudge("secrets.yml", key, &encrypted).unwrap());
+  /// ```
+  pub fn smudge(file_path: &str, key: &str, contents: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let file_encryption = FileEncryption::new(file_path.to_string(), key.to_string());
+
+    Ok(
+      file_encryption
+        .decrypt_contents(std::str::from_utf8(contents)?)?
+        .into_bytes(),
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::CipherGeneration;
+
+  #[test]
+  fn test_clean_is_deterministic() -> anyhow::Result<()> {
+    let key = CipherGeneration::random_key();
+    let first = GitFilter::clean("secrets.yml", &key, b"api_key: 123\n")?;
+
+    assert_eq!(
+      first,
+      GitFilter::clean("secrets.yml", &key, b"api_key: 123\n")?
+    );
+    assert_ne!(
+      first,
+      GitFilter::clean("secrets.yml", &key, b"api_key: 456\n")?
+    );
+    assert_ne!(
+      first,
+      GitFilter::clean(
+        "secrets.yml",
+        &CipherGeneration::random_key(),
+        b"api_key: 123\n"
+      )?
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_clean_smudge_cycle() -> anyhow::Result<()> {
+    let key = CipherGeneration::random_key();
+    let encrypted = GitFilter::clean("secrets.yml", &key, b"api_key: 123\n")?;
+
+    assert_eq!(
+      b"api_key: 123\n".to_vec(),
+      GitFilter::smudge("secrets.yml", &key, &encrypted)?
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_clean_leaves_encrypted_contents_alone() -> anyhow::Result<()> {
+    let key = CipherGeneration::random_key();
+    let encrypted = FileEncryption::new("secrets.yml".to_string(), key.clone()).encrypt(b"a: 1")?;
+
+    assert_eq!(
+      encrypted.as_bytes(),
+      GitFilter::clean("secrets.yml", &key, encrypted.as_bytes())?.as_slice()
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_smudge_with_wrong_key() -> anyhow::Result<()> {
+    let encrypted = GitFilter::clean("secrets.yml", &CipherGeneration::random_key(), b"a: 1")?;
+
+    assert!(GitFilter::smudge("secrets.yml", &CipherGeneration::random_key(), &encrypted).is_err());
+    assert!(GitFilter::smudge("secrets.yml", &CipherGeneration::random_key(), b"a: 1").is_err());
+
+    Ok(())
+  }
+}
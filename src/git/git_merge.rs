@@ -0,0 +1,438 @@
+use crate::FileEncryption;
+use serde_yaml::{Mapping, Value};
+use similar::{Algorithm, DiffTag, capture_diff_slices};
+use std::ops::Range;
+
+/// A change to a range of lines of the common ancestor.
+struct Hunk<'a> {
+  base: Range<usize>,
+  lines: &'a [&'a str],
+}
+
+/// Collection of functions that merge encrypted files for git, as a merge
+/// driver: `street-cred git merge %O %A %B`.
+pub struct GitMerge {}
+
+impl GitMerge {
+  /// Merges three versions of an encrypted file, like git's merge drivers do.
+  /// All three are decrypted, their plaintexts merged with
+  /// [GitMerge::merge_text], and the result encrypted into `ours`, keeping its
+  /// format. Returns the number of conflicts left in the merged plaintext,
+  /// surrounded by conflict markers. The result is encrypted deterministically,
+  /// like [crate::GitFilter::clean] does, so files kept in plaintext by the filter
+  /// don't show up as modified after a merge. When a file is added on both
+  /// branches, git passes an empty common ancestor, which is merged as empty
+  /// plaintext.
+  ///
+  /// # Arguments
+  /// * `base` - Path of the common ancestor (`%O`)
+  /// * `ours` - Path of the current version, which receives the result (`%A`)
+  /// * `theirs` - Path of the other branch's version (`%B`)
+  /// * `key` - Key to decrypt and encrypt the files with
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use street_cred::GitMerge;
+  ///
+  /// let key = "200a0e90e538d17390c8c4bc3bc71e44";
+  /// let conflicts = GitMerge::merge_files("base.enc", "ours.enc", "theirs.enc", key).unwrap();
+  /// ```
+  pub fn merge_files(base: &str, ours: &str, theirs: &str, key: &str) -> anyhow::Result<usize> {
+    let decrypt = |path: &str| FileEncryption::new(path.to_string(), key.to_string()).decrypt();
+    let base = match std::fs::read(base)?.trim_ascii().is_empty() {
+      true => String::new(),
+      false => decrypt(base)?,
+    };
+
+    let (merged, conflicts) = Self::merge_text(&base, &decrypt(ours)?, &decrypt(theirs)?);
+
+    FileEncryption::new(ours.to_string(), key.to_string())
+      .deterministic()
+      .write_contents(merged.as_bytes())?;
+
+    Ok(conflicts)
+  }
+
+  /// Three-way merges plaintexts line by line. When both sides change the same
+  /// lines, and all three versions are YAML mappings, the mappings are merged
+  /// key by key instead, which resolves e.g. keys added at the end of the file on
+  /// both sides. The YAML merge re-serializes the document, so it's only used
+  /// when that doesn't change any of the three versions, i.e. they have no
+  /// comments and are formatted the way they would be written. Any remaining
+  /// conflicts are surrounded by conflict markers. Returns the merged text and
+  /// the number of conflicts.
+  ///
+  /// # Arguments
+  /// * `base` - Common ancestor
+  /// * `ours` - Current version
+  /// * `theirs` - Other branch's version
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use street_cred::GitMerge;
+  ///
+  /// let base = "a: 1\nb: 2\nc: 3\n";
+  /// let ours = "a: 10\nb: 2\nc: 3\n";
+  /// let theirs = "a: 1\nb: 2\nc: 30\n";
+  ///
+  /// assert_eq!(("a: 10\nb: 2\nc: 30\n".to_string(), 0), GitMerge::merge_text(base, ours, theirs));
+  /// ```
+  pub fn merge_text(base: &str, ours: &str, theirs: &str) -> (String, usize) {
+    let (merged, conflicts) = Self::merge_lines(base, ours, theirs);
+
+    if conflicts > 0
+      && let Some(merged) = Self::merge_yaml(base, ours, theirs)
+    {
+      return (merged, 0);
+    }
+
+    (merged, conflicts)
+  }
+
+  fn merge_lines(base: &str, ours: &str, theirs: &str) -> (String, usize) {
+    let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let our_lines: Vec<&str> = ours.split_inclusive('\n').collect();
+    let their_lines: Vec<&str> = theirs.split_inclusive('\n').collect();
+
+    let our_hunks = Self::hunks(&base_lines, &our_lines);
+    let their_hunks = Self::hunks(&base_lines, &their_lines);
+
+    let mut merged = String::new();
+    let mut conflicts = 0;
+    let mut position = 0;
+    let (mut i, mut j) = (0, 0);
+
+    while i < our_hunks.len() || j < their_hunks.len() {
+      // Start a region at the earliest hunk, and grow it while hunks from either
+      // side overlap or touch it.
+      let mut region = match (our_hunks.get(i), their_hunks.get(j)) {
+        (Some(ours), Some(theirs)) if theirs.base.start < ours.base.start => theirs.base.clone(),
+        (Some(ours), _) => ours.base.clone(),
+        (None, Some(theirs)) => theirs.base.clone(),
+        (None, None) => unreachable!(),
+      };
+      let (our_start, their_start) = (i, j);
+
+      loop {
+        if let Some(hunk) = our_hunks.get(i)
+          && Self::touches(&hunk.base, &region)
+        {
+          region = region.start.min(hunk.base.start)..region.end.max(hunk.base.end);
+          i += 1;
+        } else if let Some(hunk) = their_hunks.get(j)
+          && Self::touches(&hunk.base, &region)
+        {
+          region = region.start.min(hunk.base.start)..region.end.max(hunk.base.end);
+          j += 1;
+        } else {
+          break;
+        }
+      }
+
+      merged.push_str(&base_lines[position..region.start].concat());
+
+      let our_region = Self::apply(&base_lines, &region, &our_hunks[our_start..i]);
+      let their_region = Self::apply(&base_lines, &region, &their_hunks[their_start..j]);
+
+      if our_start == i {
+        merged.push_str(&their_region);
+      } else if their_start == j || our_region == their_region {
+        merged.push_str(&our_region);
+      } else {
+        conflicts += 1;
+
+        merged.push_str("<<<<<<< ours\n");
+        Self::push_line_block(&mut merged, &our_region);
+        merged.push_str("=======\n");
+        Self::push_line_block(&mut merged, &their_region);
+        merged.push_str(">>>>>>> theirs\n");
+      }
+
+      position = region.end;
+    }
+
+    merged.push_str(&base_lines[position..].concat());
+
+    (merged, conflicts)
+  }
+
+  /// Groups the differences between `base` and `changed` into hunks.
+  fn hunks<'a>(base: &[&str], changed: &'a [&'a str]) -> Vec<Hunk<'a>> {
+    let mut hunks: Vec<Hunk<'a>> = Vec::new();
+    let mut pending: Option<(Range<usize>, Range<usize>)> = None;
+
+    for op in capture_diff_slices(Algorithm::Myers, base, changed) {
+      let (tag, base_range, changed_range) = op.as_tag_tuple();
+
+      if tag == DiffTag::Equal {
+        if let Some((base_range, changed_range)) = pending.take() {
+          hunks.push(Hunk {
+            base: base_range,
+            lines: &changed[changed_range],
+          });
+        }
+
+        continue;
+      }
+
+      pending = Some(match pending {
+        Some((pending_base, pending_changed)) => (
+          pending_base.start..base_range.end,
+          pending_changed.start..changed_range.end,
+        ),
+        None => (base_range, changed_range),
+      });
+    }
+
+    if let Some((base_range, changed_range)) = pending {
+      hunks.push(Hunk {
+        base: base_range,
+        lines: &changed[changed_range],
+      });
+    }
+
+    hunks
+  }
+
+  /// Hunks that touch are merged into one region, like git does, so changes to
+  /// adjacent lines conflict.
+  fn touches(hunk: &Range<usize>, region: &Range<usize>) -> bool {
+    hunk.start <= region.end && region.start <= hunk.end
+  }
+
+  /// Returns the lines of `region` of the base with `hunks` applied.
+  fn apply(base: &[&str], region: &Range<usize>, hunks: &[Hunk]) -> String {
+    let mut contents = String::new();
+    let mut position = region.start;
+
+    for hunk in hunks {
+      contents.push_str(&base[position..hunk.base.start].concat());
+      contents.push_str(&hunk.lines.concat());
+      position = hunk.base.end;
+    }
+
+    contents.push_str(&base[position..region.end].concat());
+
+    contents
+  }
+
+  fn push_line_block(merged: &mut String, block: &str) {
+    merged.push_str(block);
+
+    if !block.is_empty() && !block.ends_with('\n') {
+      merged.push('\n');
+    }
+  }
+
+  fn merge_yaml(base: &str, ours: &str, theirs: &str) -> Option<String> {
+    // Only documents that serialize back to the same text can be merged without
+    // losing comments or formatting.
+    let parse = |contents: &str| match serde_yaml::from_str::<Value>(contents) {
+      Ok(Value::Mapping(mapping))
+        if serde_yaml::to_string(&mapping).is_ok_and(|written| written == contents) =>
+      {
+        Some(mapping)
+      }
+      Ok(Value::Null) if contents.trim().is_empty() => Some(Mapping::new()),
+      _ => None,
+    };
+
+    let merged = Self::merge_mappings(&parse(base)?, &parse(ours)?, &parse(theirs)?)?;
+
+    serde_yaml::to_string(&merged).ok()
+  }
+
+  fn merge_mappings(base: &Mapping, ours: &Mapping, theirs: &Mapping) -> Option<Mapping> {
+    let mut merged = Mapping::new();
+    let keys = ours
+      .keys()
+      .chain(theirs.keys().filter(|key| !ours.contains_key(*key)))
+      .chain(
+        base
+          .keys()
+          .filter(|key| !ours.contains_key(*key) && !theirs.contains_key(*key)),
+      );
+
+    for key in keys {
+      let value = match (base.get(key), ours.get(key), theirs.get(key)) {
+        (_, ours, theirs) if ours == theirs => ours.cloned(),
+        (base, ours, theirs) if base == ours => theirs.cloned(),
+        (base, ours, theirs) if base == theirs => ours.cloned(),
+        (base, Some(Value::Mapping(ours)), Some(Value::Mapping(theirs))) => {
+          let empty = Mapping::new();
+          let base = match base {
+            Some(Value::Mapping(base)) => base,
+            _ => &empty,
+          };
+
+          Some(Value::Mapping(Self::merge_mappings(base, ours, theirs)?))
+        }
+        _ => return None,
+      };
+
+      if let Some(value) = value {
+        merged.insert(key.clone(), value);
+      }
+    }
+
+    Some(merged)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::CipherGeneration;
+  use assert_fs::prelude::*;
+
+  #[test]
+  fn test_merge_text_non_overlapping_changes() {
+    let base = "a: 1\nb: 2\nc: 3\nd: 4\n";
+    let ours = "a: 10\nb: 2\nc: 3\nd: 4\n";
+    let theirs = "a: 1\nb: 2\nc: 3\nd: 40\ne: 5\n";
+
+    assert_eq!(
+      ("a: 10\nb: 2\nc: 3\nd: 40\ne: 5\n".to_string(), 0),
+      GitMerge::merge_text(base, ours, theirs)
+    );
+  }
+
+  #[test]
+  fn test_merge_text_same_change_on_both_sides() {
+    let base = "a: 1\n";
+    let ours = "a: 2\n";
+
+    assert_eq!(
+      ("a: 2\n".to_string(), 0),
+      GitMerge::merge_text(base, ours, ours)
+    );
+  }
+
+  #[test]
+  fn test_merge_text_resolves_yaml_additions() {
+    let base = "aws:\n  key: 1\n";
+    let ours = "aws:\n  key: 1\n  secret: 2\n";
+    let theirs = "aws:\n  key: 1\nstripe: 3\n";
+
+    let (merged, conflicts) = GitMerge::merge_text(base, ours, theirs);
+
+    assert_eq!(0, conflicts);
+    assert_eq!("aws:\n  key: 1\n  secret: 2\nstripe: 3\n", merged);
+  }
+
+  #[test]
+  fn test_merge_text_keeps_comments_instead_of_merging_yaml() {
+    let base = "# aws:\n#   key: example\naws:\n  key: 1\n";
+    let ours = "# aws:\n#   key: example\naws:\n  key: 1\n  secret: 2\n";
+    let theirs = "# aws:\n#   key: example\naws:\n  key: 1\nstripe: 3\n";
+
+    let (merged, conflicts) = GitMerge::merge_text(base, ours, theirs);
+
+    assert_eq!(1, conflicts);
+    assert_eq!(
+      "# aws:\n#   key: example\naws:\n  key: 1\n<<<<<<< ours\n  secret: 2\n=======\nstripe: 3\n>>>>>>> theirs\n",
+      merged
+    );
+  }
+
+  #[test]
+  fn test_merge_text_leaves_conflict_markers() {
+    let base = "a: 1\nb: 2\n";
+    let ours = "a: 10\nb: 2\n";
+    let theirs = "a: 11\nb: 2\n";
+
+    assert_eq!(
+      (
+        "<<<<<<< ours\na: 10\n=======\na: 11\n>>>>>>> theirs\nb: 2\n".to_string(),
+        1
+      ),
+      GitMerge::merge_text(base, ours, theirs)
+    );
+  }
+
+  #[test]
+  fn test_merge_text_without_trailing_newline() {
+    let (merged, conflicts) = GitMerge::merge_text("CHANGE ME", "ours", "theirs");
+
+    assert_eq!(1, conflicts);
+    assert_eq!(
+      "<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\n",
+      merged
+    );
+  }
+
+  #[test]
+  fn test_merge_text_deletions() {
+    let base = "a: 1\nb: 2\nc: 3\n";
+    let ours = "b: 2\nc: 3\n";
+    let theirs = "a: 1\nb: 2\n";
+
+    assert_eq!(
+      ("b: 2\n".to_string(), 0),
+      GitMerge::merge_text(base, ours, theirs)
+    );
+  }
+
+  #[test]
+  fn test_merge_files() -> anyhow::Result<()> {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let key = CipherGeneration::random_key();
+    let mut paths = Vec::new();
+
+    for (name, contents) in [
+      ("base.enc", "a: 1\nb: 2\nc: 3\n"),
+      ("ours.enc", "a: 10\nb: 2\nc: 3\n"),
+      ("theirs.enc", "a: 1\nb: 2\nc: 30\n"),
+    ] {
+      let path = temp.child(name).to_string_lossy().to_string();
+      let file_encryption = FileEncryption::new(path.clone(), key.clone());
+
+      temp
+        .child(name)
+        .write_str(&file_encryption.encrypt(contents.as_bytes())?)?;
+      paths.push(path);
+    }
+
+    let conflicts = GitMerge::merge_files(&paths[0], &paths[1], &paths[2], &key)?;
+
+    assert_eq!(0, conflicts);
+    assert_eq!(
+      "a: 10\nb: 2\nc: 30\n",
+      FileEncryption::new(paths[1].clone(), key).decrypt()?
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_merge_files_added_on_both_sides() -> anyhow::Result<()> {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let key = CipherGeneration::random_key();
+    let base = temp.child("base.enc");
+    let mut paths = Vec::new();
+
+    base.write_str("")?;
+
+    for (name, contents) in [("ours.enc", "a: 1\n"), ("theirs.enc", "a: 2\n")] {
+      let path = temp.child(name).to_string_lossy().to_string();
+      let file_encryption = FileEncryption::new(path.clone(), key.clone());
+
+      temp
+        .child(name)
+        .write_str(&file_encryption.encrypt(contents.as_bytes())?)?;
+      paths.push(path);
+    }
+
+    let conflicts = GitMerge::merge_files(&base.to_string_lossy(), &paths[0], &paths[1], &key)?;
+    let merged = FileEncryption::new(paths[0].clone(), key).decrypt()?;
+
+    assert_eq!(1, conflicts);
+    assert!(merged.contains("<<<<<<<"));
+    assert!(merged.contains("a: 1\n"));
+    assert!(merged.contains("a: 2\n"));
+
+    Ok(())
+  }
+}
@@ -0,0 +1,98 @@
+use anyhow::{Context, anyhow};
+use std::path::Path;
+use std::process::Command;
+
+/// Collection of functions that read files as git stores them.
+pub struct GitRevision {}
+
+impl GitRevision {
+  /// Returns the contents of a file at a revision, as stored by git. An empty
+  /// revision reads the file as staged in the index.
+  ///
+  /// # Arguments
+  /// * `directory` - Directory inside the git repository
+  /// * `revision` - Revision to read the file at, e.g. `HEAD~1`
+  /// * `path` - Path of the file, relative to `directory`
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use std::path::Path;
+  /// use street_cred::GitRevision;
+  ///
+  /// let contents = GitRevision::contents(Path::new("."), "HEAD~1", Path::new("credentials.yml.enc"));
+  /// ```
+  pub fn contents(directory: &Path, revision: &str, path: &Path) -> anyhow::Result<Vec<u8>> {
+    let output = Command::new("git")
+      .arg("-C")
+      .arg(directory)
+      .arg("show")
+      .arg(format!("{}:./{}", revision, path.to_string_lossy()))
+      .output()
+      .context("Could not run git")?;
+
+    if !output.status.success() {
+      return Err(anyhow!(
+        "Could not read {} at {}: {}",
+        path.display(),
+        match revision {
+          "" => "the index",
+          revision => revision,
+        },
+        String::from_utf8_lossy(&output.stderr).trim()
+      ));
+    }
+
+    Ok(output.stdout)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use assert_fs::prelude::*;
+
+  fn git(repository: &Path, args: &[&str]) {
+    Command::new("git")
+      .arg("-C")
+      .arg(repository)
+      .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+      .args(args)
+      .output()
+      .unwrap();
+  }
+
+  #[test]
+  fn test_contents_at_revisions() -> anyhow::Result<()> {
+    let repository = assert_fs::TempDir::new()?;
+    let file = repository.child("config/credentials.yml.enc");
+
+    git(repository.path(), &["init", "-q"]);
+    file.write_str("first")?;
+    git(repository.path(), &["add", "-A"]);
+    git(repository.path(), &["commit", "-q", "-m", "first"]);
+    file.write_str("second")?;
+    git(repository.path(), &["commit", "-q", "-a", "-m", "second"]);
+    file.write_str("staged")?;
+    git(repository.path(), &["add", "-A"]);
+
+    let config = repository.child("config");
+    let path = Path::new("credentials.yml.enc");
+
+    assert_eq!(
+      b"first".to_vec(),
+      GitRevision::contents(config.path(), "HEAD~1", path)?
+    );
+    assert_eq!(
+      b"second".to_vec(),
+      GitRevision::contents(config.path(), "HEAD", path)?
+    );
+    assert_eq!(
+      b"staged".to_vec(),
+      GitRevision::contents(config.path(), "", path)?
+    );
+    assert!(GitRevision::contents(config.path(), "HEAD~5", path).is_err());
+
+    Ok(())
+  }
+}
@@ -0,0 +1,464 @@
+use anyhow::{Context, anyhow};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Name of the git diff and merge drivers and filter, as used in `.gitattributes`.
+static DRIVER_NAME: &str = "street-cred";
+/// Comment identifying hooks installed by street-cred.
+static HOOK_MARKER: &str = "Installed by street-cred";
+
+/// Collection of functions that hook street-cred into git, so encrypted files
+/// can be worked with like plaintext ones by anyone holding the key.
+pub struct GitSetup {}
+
+impl GitSetup {
+  /// Registers the `street-cred` diff driver in the repository's local git
+  /// config, with `command` as its textconv program, and marks files matching
+  /// `pattern` to use it in `.gitattributes`. `git diff` and `git log -p` then show
+  /// plaintext diffs of encrypted files. Returns the lines added to
+  /// `.gitattributes`.
+  ///
+  /// # Arguments
+  /// * `repository` - Root directory of the git repository
+  /// * `pattern` - `.gitattributes` pattern matching the encrypted files
+  /// * `command` - Command git runs with the path of an encrypted file, printing
+  ///   its plaintext
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use std::path::Path;
+  /// use street_cred::GitSetup;
+  ///
+  /// let result = GitSetup::install_textconv(Path::new("."), "*.enc", "street-cred git textconv");
+  /// ```
+  pub fn install_textconv(
+    repository: &Path,
+    pattern: &str,
+    command: &str,
+  ) -> anyhow::Result<Vec<String>> {
+    Self::git_config(
+      repository,
+      &format!("diff.{}.textconv", DRIVER_NAME),
+      command,
+    )?;
+
+    Self::append_attributes(repository, &[format!("{} diff={}", pattern, DRIVER_NAME)])
+  }
+
+  /// Registers the `street-cred` merge driver in the repository's local git
+  /// config, with `command` as its driver, and marks files matching `pattern` to
+  /// use it in `.gitattributes`. Merges of encrypted files then merge their
+  /// plaintexts instead of failing on the ciphertext. Returns the lines added to
+  /// `.gitattributes`.
+  ///
+  /// # Arguments
+  /// * `repository` - Root directory of the git repository
+  /// * `pattern` - `.gitattributes` pattern matching the encrypted files
+  /// * `command` - Command git runs to merge, with `%O`, `%A` and `%B` replaced by
+  ///   the paths of the ancestor's, current and other branch's versions
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use std::path::Path;
+  /// use street_cred::GitSetup;
+  ///
+  /// let result =
+  ///   GitSetup::install_merge_driver(Path::new("."), "*.enc", "street-cred git merge %O %A %B");
+  /// ```
+  pub fn install_merge_driver(
+    repository: &Path,
+    pattern: &str,
+    command: &str,
+  ) -> anyhow::Result<Vec<String>> {
+    Self::git_config(
+      repository,
+      &format!("merge.{}.name", DRIVER_NAME),
+      "street-cred encrypted file merge",
+    )?;
+    Self::git_config(
+      repository,
+      &format!("merge.{}.driver", DRIVER_NAME),
+      command,
+    )?;
+
+    Self::append_attributes(repository, &[format!("{} merge={}", pattern, DRIVER_NAME)])
+  }
+
+  /// Registers the `street-cred` clean/smudge filter in the repository's local
+  /// git config and marks files matching `pattern` to use it in
+  /// `.gitattributes`. Those files are then kept in plaintext in the working
+  /// tree, while git stores them encrypted. The filter is marked as required, so
+  /// git refuses to store files it couldn't encrypt rather than storing their
+  /// plaintext. Returns the lines added to `.gitattributes`.
+  ///
+  /// # Arguments
+  /// * `repository` - Root directory of the git repository
+  /// * `pattern` - `.gitattributes` pattern matching the plaintext files
+  /// * `clean` - Command encrypting the plaintext on stdin to stdout
+  /// * `smudge` - Command decrypting the encrypted contents on stdin to stdout
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use std::path::Path;
+  /// use street_cred::GitSetup;
+  ///
+  /// let result = GitSetup::install_filter(
+  ///   Path::new("."),
+  ///   "secrets.yml",
+  ///   "street-cred git clean %f",
+  ///   "street-cred git smudge %f",
+  /// );
+  /// ```
+  pub fn install_filter(
+    repository: &Path,
+    pattern: &str,
+    clean: &str,
+    smudge: &str,
+  ) -> anyhow::Result<Vec<String>> {
+    Self::git_config(repository, &format!("filter.{}.clean", DRIVER_NAME), clean)?;
+    Self::git_config(
+      repository,
+      &format!("filter.{}.smudge", DRIVER_NAME),
+      smudge,
+    )?;
+    Self::git_config(
+      repository,
+      &format!("filter.{}.required", DRIVER_NAME),
+      "true",
+    )?;
+
+    Self::append_attributes(repository, &[format!("{} filter={}", pattern, DRIVER_NAME)])
+  }
+
+  /// Installs a pre-commit hook running `command`, which refuses the commit when
+  /// it fails. An existing hook is left alone, unless it's one installed by
+  /// street-cred. Returns the path of the hook.
+  ///
+  /// # Arguments
+  /// * `repository` - Root directory of the git repository
+  /// * `command` - Command checking the staged files, e.g. `street-cred scan --staged`
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use std::path::Path;
+  /// use street_cred::GitSetup;
+  ///
+  /// let hook_path = GitSetup::install_pre_commit_hook(Path::new("."), "street-cred scan --staged");
+  /// ```
+  pub fn install_pre_commit_hook(repository: &Path, command: &str) -> anyhow::Result<PathBuf> {
+    let output = Command::new("git")
+      .arg("-C")
+      .arg(repository)
+      .args(["rev-parse", "--git-path", "hooks"])
+      .output()
+      .context("Could not run git")?;
+
+    if !output.status.success() {
+      return Err(anyhow!(
+        "{} is not inside a git repository",
+        repository.display()
+      ));
+    }
+
+    let hooks_path = repository.join(String::from_utf8(output.stdout)?.trim());
+    let hook_path = hooks_path.join("pre-commit");
+
+    match fs::read_to_string(&hook_path) {
+      Ok(existing) if !existing.contains(HOOK_MARKER) => {
+        return Err(anyhow!(
+          "{} already exists, add `{}` to it instead",
+          hook_path.display(),
+          command
+        ));
+      }
+      Ok(_) => {}
+      Err(why) if why.kind() == io::ErrorKind::NotFound => {}
+      Err(why) => return Err(why.into()),
+    }
+
+    fs::create_dir_all(&hooks_path)?;
+    fs::write(
+      &hook_path,
+      format!("#!/bin/sh\n# {}\nexec {}\n", HOOK_MARKER, command),
+    )?;
+
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+
+      fs::set_permissions(&hook_path, fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(hook_path)
+  }
+
+  /// Returns the root directory of the git repository `directory` is part of.
+  ///
+  /// # Arguments
+  /// * `directory` - Directory inside the repository
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use std::path::Path;
+  /// use street_cred::GitSetup;
+  ///
+  /// let root = GitSetup::repository_root(Path::new("."));
+  /// ```
+  pub fn repository_root(directory: &Path) -> anyhow::Result<PathBuf> {
+    let output = Command::new("git")
+      .arg("-C")
+      .arg(directory)
+      .args(["rev-parse", "--show-toplevel"])
+      .output()
+      .context("Could not run git")?;
+
+    if !output.status.success() {
+      return Err(anyhow!(
+        "{} is not inside a git repository",
+        directory.display()
+      ));
+    }
+
+    Ok(PathBuf::from(String::from_utf8(output.stdout)?.trim()))
+  }
+
+  /// Sets `key` to `value` in the repository's local git config.
+  fn git_config(repository: &Path, key: &str, value: &str) -> anyhow::Result<()> {
+    let status = Command::new("git")
+      .arg("-C")
+      .arg(repository)
+      .args(["config", "--local", key, value])
+      .status()
+      .context("Could not run git")?;
+
+    if status.success() {
+      Ok(())
+    } else {
+      Err(anyhow!("Could not set {} in the git config", key))
+    }
+  }
+
+  /// Appends the lines missing from the repository's `.gitattributes` and
+  /// returns them.
+  fn append_attributes(repository: &Path, lines: &[String]) -> anyhow::Result<Vec<String>> {
+    let attributes_path = repository.join(".gitattributes");
+    let existing = match fs::read_to_string(&attributes_path) {
+      Ok(existing) => existing,
+      Err(why) if why.kind() == io::ErrorKind::NotFound => String::new(),
+      Err(why) => return Err(why.into()),
+    };
+
+    let missing: Vec<String> = lines
+      .iter()
+      .filter(|line| {
+        !existing
+          .lines()
+          .any(|existing| existing.trim() == line.as_str())
+      })
+      .cloned()
+      .collect();
+
+    if !missing.is_empty() {
+      let mut contents = String::new();
+
+      if !existing.is_empty() && !existing.ends_with('\n') {
+        contents.push('\n');
+      }
+
+      for line in &missing {
+        contents.push_str(line);
+        contents.push('\n');
+      }
+
+      fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(attributes_path)?
+        .write_all(contents.as_bytes())?;
+    }
+
+    Ok(missing)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use assert_fs::prelude::*;
+
+  fn git_repository() -> assert_fs::TempDir {
+    let temp = assert_fs::TempDir::new().unwrap();
+
+    Command::new("git")
+      .arg("init")
+      .arg("-q")
+      .arg(temp.path())
+      .status()
+      .unwrap();
+
+    temp
+  }
+
+  fn git_config_value(repository: &Path, key: &str) -> String {
+    let output = Command::new("git")
+      .arg("-C")
+      .arg(repository)
+      .args(["config", "--get", key])
+      .output()
+      .unwrap();
+
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+  }
+
+  #[test]
+  fn test_install_textconv() -> anyhow::Result<()> {
+    let repository = git_repository();
+
+    repository
+      .child(".gitattributes")
+      .write_str("*.png binary")?;
+
+    let added = GitSetup::install_textconv(repository.path(), "*.enc", "street-cred git textconv")?;
+
+    assert_eq!(vec!["*.enc diff=street-cred"], added);
+    assert_eq!(
+      "street-cred git textconv",
+      git_config_value(repository.path(), "diff.street-cred.textconv")
+    );
+    assert_eq!(
+      "*.png binary\n*.enc diff=street-cred\n",
+      fs::read_to_string(repository.child(".gitattributes"))?
+    );
+
+    let added_again =
+      GitSetup::install_textconv(repository.path(), "*.enc", "street-cred git textconv")?;
+
+    assert!(added_again.is_empty());
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_install_merge_driver() -> anyhow::Result<()> {
+    let repository = git_repository();
+
+    GitSetup::install_textconv(repository.path(), "*.enc", "street-cred git textconv")?;
+
+    let added =
+      GitSetup::install_merge_driver(repository.path(), "*.enc", "street-cred git merge %O %A %B")?;
+
+    assert_eq!(vec!["*.enc merge=street-cred"], added);
+    assert_eq!(
+      "street-cred git merge %O %A %B",
+      git_config_value(repository.path(), "merge.street-cred.driver")
+    );
+    assert_eq!(
+      "*.enc diff=street-cred\n*.enc merge=street-cred\n",
+      fs::read_to_string(repository.child(".gitattributes"))?
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_install_filter() -> anyhow::Result<()> {
+    let repository = git_repository();
+
+    let added = GitSetup::install_filter(
+      repository.path(),
+      "secrets.yml",
+      "street-cred git clean %f",
+      "street-cred git smudge %f",
+    )?;
+
+    assert_eq!(vec!["secrets.yml filter=street-cred"], added);
+    assert_eq!(
+      "street-cred git clean %f",
+      git_config_value(repository.path(), "filter.street-cred.clean")
+    );
+    assert_eq!(
+      "street-cred git smudge %f",
+      git_config_value(repository.path(), "filter.street-cred.smudge")
+    );
+    assert_eq!(
+      "true",
+      git_config_value(repository.path(), "filter.street-cred.required")
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_install_pre_commit_hook() -> anyhow::Result<()> {
+    let repository = git_repository();
+
+    let hook_path =
+      GitSetup::install_pre_commit_hook(repository.path(), "street-cred scan --staged")?;
+    let hook = fs::read_to_string(&hook_path)?;
+
+    assert!(hook_path.ends_with(".git/hooks/pre-commit"));
+    assert!(hook.starts_with("#!/bin/sh\n"));
+    assert!(hook.ends_with("exec street-cred scan --staged\n"));
+
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+
+      assert_eq!(
+        0o755,
+        fs::metadata(&hook_path)?.permissions().mode() & 0o777
+      );
+    }
+
+    assert!(
+      GitSetup::install_pre_commit_hook(repository.path(), "street-cred scan --staged").is_ok()
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_install_pre_commit_hook_keeps_existing_hook() -> anyhow::Result<()> {
+    let repository = git_repository();
+    let hook = repository.child(".git/hooks/pre-commit");
+
+    hook.write_str("#!/bin/sh\nmake lint\n")?;
+
+    assert!(
+      GitSetup::install_pre_commit_hook(repository.path(), "street-cred scan --staged").is_err()
+    );
+    assert_eq!("#!/bin/sh\nmake lint\n", fs::read_to_string(hook.path())?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_repository_root() -> anyhow::Result<()> {
+    let repository = git_repository();
+    let subdirectory = repository.child("config/credentials");
+
+    subdirectory.create_dir_all()?;
+
+    assert_eq!(
+      fs::canonicalize(repository.path())?,
+      fs::canonicalize(GitSetup::repository_root(subdirectory.path())?)?
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_install_textconv_outside_repository() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let not_repository = temp.child("missing");
+
+    assert!(GitSetup::install_textconv(not_repository.path(), "*.enc", "street-cred").is_err());
+  }
+}
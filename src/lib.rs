@@ -39,16 +39,51 @@
 //! Once you've added the crate to your project, you can import various parts of it to start encrypting
 //! your data.
 //!
-//! Encrypting/Decrypting files can be accomplished with [FileEncryption].
+//! Encrypting/Decrypting files can be accomplished with [FileEncryption]. [KeyLookup] finds the key
+//! of an encrypted file the same way the cli does.
 //!
 //! Encrypting/Decrypting data directly can be accomplished using [MessageEncryption]. While using
 //! MessageEncryption, you'll need to provide some data for the encryption process like the
 //! encryption key and additional authenticated data. street-cred provides a few utility functions for this
-//! data via [CipherGeneration].
+//! data via [CipherGeneration]. Serializers and archive builders can write to an encrypted message
+//! directly through [MessageEncryption::encrypting_writer], and read it back through
+//! [MessageEncryption::decrypting_reader]. Keys for Rails cookies and messages can be derived from an
+//! application's `secret_key_base` with [KeyGenerator].
+//!
+//! Messages produced by Rails before 5.2 (AES-256-CBC signed with an HMAC) can be handled with
+//! [LegacyMessageEncryption].
+//!
+//! Encrypted cookies set by a Rails application can be read and written with [RailsCookie].
+//! Signed (but not encrypted) tokens, like those from `ActiveSupport::MessageVerifier`, can be
+//! generated and verified with [MessageVerifier]. Column values written by ActiveRecord
+//! Encryption can be read and written with [ActiveRecordEncryption].
+//!
+//! Large payloads can be compressed before they are encrypted, see [Compression]. Besides the
+//! AES-GCM ciphers Rails uses, data can be encrypted with XChaCha20-Poly1305 or AES-256-GCM-SIV, see [Cipher].
+//! Files too large to hold in memory, like database dumps, can be encrypted in chunks with
+//! [StreamEncryptor] and decrypted with [StreamDecryptor].
+//!
+//! The git integration of the cli (plaintext diffs, merge drivers, clean/smudge filters and
+//! scanning for leaked secrets) is built with the default `git` feature. Its types are exported
+//! for the cli, but they aren't a stable API and may change in any release, so depend on
+//! street-cred with `default-features = false` if you don't need them. [PlaintextDiff] shows how
+//! the plaintext of encrypted files changed, optionally without revealing any values.
+//! YAML credentials can be shown without their values with [Redaction].
 //!
 
+mod diff;
 mod encryption;
+#[cfg(feature = "git")]
+mod git;
 mod serialization;
 
-pub use crate::encryption::{CipherGeneration, FileEncryption, MessageEncryption};
-pub use crate::serialization::RubyMarshal;
+pub use crate::diff::PlaintextDiff;
+pub use crate::encryption::{
+  ActiveRecordEncryption, Cipher, CipherGeneration, Compression, CookiePayload, CreateOptions,
+  CreatedFiles, DecryptingReader, Digest, EncryptingWriter, FileEncryption, KeyFormat,
+  KeyGenerator, KeyLookup, KeySource, LegacyMessageEncryption, MessageEncryption, MessageVerifier,
+  RailsCookie, StreamDecryptor, StreamEncryptor,
+};
+#[cfg(feature = "git")]
+pub use crate::git::{GitFilter, GitMerge, GitRevision, GitScan, GitSetup, ScanFinding};
+pub use crate::serialization::{Redaction, RubyMarshal};
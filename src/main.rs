@@ -1,7 +1,15 @@
 #![cfg(not(tarpaulin_include))]
-use anyhow::anyhow;
+use anyhow::{Context, anyhow};
+use clap::builder::{PossibleValuesParser, TypedValueParser as _};
 use clap::{Args, Parser, Subcommand};
-use street_cred::FileEncryption;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use street_cred::{
+  Cipher, CipherGeneration, Compression, CreateOptions, CreatedFiles, FileEncryption, GitFilter,
+  GitMerge, GitRevision, GitScan, GitSetup, KeyFormat, KeyLookup, KeySource, MessageEncryption,
+  PlaintextDiff, Redaction, ScanFinding, StreamDecryptor, StreamEncryptor,
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -9,31 +17,288 @@ use street_cred::FileEncryption;
 struct Cli {
   #[command(subcommand)]
   command: Commands,
+  /// Key file to use instead of MASTER_KEY, a key named after the encrypted
+  /// file (production.yml.enc uses production.key) or master.key. With init,
+  /// the name of the key file to create.
+  #[arg(long, global = true, value_name = "PATH")]
+  key_file: Option<PathBuf>,
 }
 
+/// Key file given with --key-file, read by every command that needs the key.
+static KEY_FILE: OnceLock<PathBuf> = OnceLock::new();
+
 #[derive(Subcommand)]
 enum Commands {
+  /// Decrypt a message from stdin to stdout
+  Decrypt(Decrypt),
+  /// Show how the plaintext of encrypted files changed
+  Diff(Diff),
   /// Read contents of a file
   Edit(Edit),
+  /// Encrypt UTF-8 text from stdin to stdout as "message--iv--tag", like Rails
+  /// does. Use --stream for binary data.
+  Encrypt(Encrypt),
   /// Initialize new secrets file
   Init(Init),
+  /// Integrate encrypted files with git
+  Git(Git),
+  /// Manage the master key
+  Key(Key),
+  /// Look for keys and plaintext secrets that shouldn't be committed
+  Scan(Scan),
+  /// Print the decrypted contents of a file
+  Show(Show),
 }
 
 #[derive(Args)]
 struct Edit {
   file_name: String,
+  /// Compress contents before encrypting them (none, zlib or zstd)
+  #[arg(long, default_value_t = Compression::None)]
+  compression: Compression,
+  /// Size in bytes above which contents are compressed
+  #[arg(long, default_value_t = 1024)]
+  compression_threshold: usize,
+  /// Cipher to re-encrypt with (aes-128-gcm, aes-256-gcm, aes-256-gcm-siv or
+  /// xchacha20-poly1305).
+  /// Defaults to the cipher the file was encrypted with.
+  #[arg(long)]
+  cipher: Option<Cipher>,
+  /// Record the key fingerprint in the file, to tell a wrong key apart from a
+  /// corrupted file. Rails can't read files with a recorded key.
+  #[arg(long)]
+  record_key_id: bool,
+}
+
+#[derive(Args)]
+struct Decrypt {
+  /// Decrypt a binary stream written by encrypt --stream
+  #[arg(long)]
+  stream: bool,
+}
+
+#[derive(Args)]
+struct Diff {
+  /// Two encrypted files to compare, or one with --rev
+  #[arg(required = true, num_args = 1..=2)]
+  file_names: Vec<String>,
+  /// Compare the file with its version at this git revision, e.g. HEAD~1. A
+  /// working file that doesn't decrypt, like one kept in plaintext by
+  /// `git setup --filter`, is compared as is
+  #[arg(long)]
+  rev: Option<String>,
+  /// Show changed YAML keys and their values instead of a unified diff
+  #[arg(long)]
+  yaml: bool,
+  /// Show only which YAML keys changed, without any values (requires --yaml)
+  #[arg(long, requires = "yaml")]
+  mask: bool,
+}
+
+#[derive(Args)]
+struct Encrypt {
+  /// Cipher to encrypt with (aes-128-gcm, aes-256-gcm, aes-256-gcm-siv or
+  /// xchacha20-poly1305). Defaults to AES-GCM, picked from the key length.
+  #[arg(long)]
+  cipher: Option<Cipher>,
+  /// Encrypt any amount of binary data in chunks, without reading it into
+  /// memory. Decrypt it with decrypt --stream.
+  #[arg(long)]
+  stream: bool,
+}
+
+#[derive(Args)]
+struct Init {
+  /// Cipher to encrypt with (aes-128-gcm, aes-256-gcm, aes-256-gcm-siv or
+  /// xchacha20-poly1305)
+  #[arg(long, default_value_t = Cipher::Aes128Gcm)]
+  cipher: Cipher,
+  /// Don't add master.key and the plaintext file to .gitignore
+  #[arg(long)]
+  no_gitignore: bool,
+  /// File whose contents start the credentials file
+  #[arg(long)]
+  template: Option<PathBuf>,
+  /// Start from the Rails template, with a generated secret_key_base
+  #[arg(long, conflicts_with = "template")]
+  rails_template: bool,
+  /// Name of the credentials file to create
+  #[arg(long, default_value = "credentials.yml.enc")]
+  file: String,
+}
+
+#[derive(Args)]
+struct Scan {
+  /// Files or directories to scan. Defaults to the files git could commit.
+  paths: Vec<PathBuf>,
+  /// Scan the files staged for the next commit instead
+  #[arg(long, conflicts_with = "paths")]
+  staged: bool,
+}
+
+#[derive(Args)]
+struct Show {
+  file_name: String,
+  /// Show only the YAML structure, with values replaced by their type and
+  /// length (or a hash keyed by the master key, with --redact=hash)
+  #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "length")]
+  redact: Option<Redaction>,
+}
+
+#[derive(Args)]
+struct Git {
+  #[command(subcommand)]
+  command: GitCommands,
+}
+
+#[derive(Subcommand)]
+enum GitCommands {
+  /// Encrypt a plaintext working file from stdin, as git's clean filter
+  Clean(GitFilterArgs),
+  /// Manage git hooks
+  Hook(GitHook),
+  /// Merge three versions of an encrypted file, as git's merge driver
+  Merge(GitMergeArgs),
+  /// Register street-cred with git so `git diff` shows decrypted changes and
+  /// merges merge the plaintext
+  Setup(GitSetupArgs),
+  /// Decrypt a file stored by git from stdin, as git's smudge filter
+  Smudge(GitFilterArgs),
+  /// Print the decrypted contents of a file, for git's diff textconv
+  Textconv(GitTextconv),
+}
+
+#[derive(Args)]
+struct GitSetupArgs {
+  /// .gitattributes pattern matching the encrypted files
+  #[arg(long, default_value = "*.enc")]
+  pattern: String,
+  /// .gitattributes pattern matching plaintext files to keep decrypted in the
+  /// working tree, while git stores them encrypted
+  #[arg(long)]
+  filter: Option<String>,
+}
+
+#[derive(Args)]
+struct GitHook {
+  #[command(subcommand)]
+  command: GitHookCommands,
+}
+
+#[derive(Subcommand)]
+enum GitHookCommands {
+  /// Install a pre-commit hook refusing commits of keys and plaintext secrets
+  Install,
+}
+
+#[derive(Args)]
+struct GitFilterArgs {
+  /// Path of the working file (%f)
+  file_name: String,
+}
+
+#[derive(Args)]
+struct GitMergeArgs {
+  /// Common ancestor's version (%O)
+  base: String,
+  /// Current version, which receives the merge result (%A)
+  ours: String,
+  /// Other branch's version (%B)
+  theirs: String,
+  /// Path of the merged file (%P), to find the key named after it
+  path: Option<String>,
+}
+
+#[derive(Args)]
+struct GitTextconv {
+  file_name: String,
+}
+
+#[derive(Args)]
+struct Key {
+  #[command(subcommand)]
+  command: KeyCommands,
+}
+
+#[derive(Subcommand)]
+enum KeyCommands {
+  /// Convert a key between hex, base64 and raw bytes
+  Convert(KeyConvert),
+  /// Print the fingerprint of the master key
+  Fingerprint,
+  /// Generate a new random key
+  Generate(KeyGenerate),
+  /// Show where the master key is read from
+  Path,
+  /// Check that the master key decrypts a file
+  Verify(KeyVerify),
+}
+
+#[derive(Args)]
+struct KeyConvert {
+  /// Key to convert, or "-" to read it from stdin. Defaults to the master key.
+  key: Option<String>,
+  /// Format of the key to convert (hex, base64 or raw)
+  #[arg(long, default_value_t = KeyFormat::Hex)]
+  from: KeyFormat,
+  /// Format to convert the key to (hex, base64 or raw)
+  #[arg(long)]
+  to: KeyFormat,
+}
+
+#[derive(Args)]
+struct KeyGenerate {
+  /// Size of the key in bits
+  #[arg(
+    long,
+    default_value_t = 128,
+    value_parser = PossibleValuesParser::new(["128", "256"]).map(|bits| bits.parse::<u16>().unwrap())
+  )]
+  bits: u16,
+  /// Write the key to this file instead of printing it
+  #[arg(long)]
+  out: Option<PathBuf>,
 }
 
 #[derive(Args)]
-struct Init {}
+struct KeyVerify {
+  file_name: String,
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
   let cli = Cli::parse();
 
+  if let (Some(key_file), false) = (&cli.key_file, matches!(cli.command, Commands::Init(_))) {
+    let _ = KEY_FILE.set(key_file.clone());
+  }
+
   match cli.command {
-    Commands::Edit(file) => match retrieve_encryption_key() {
+    Commands::Decrypt(decrypt) => {
+      if let Err(why) = decrypt_stdin(&decrypt) {
+        eprintln!("{}", why);
+        std::process::exit(1);
+      }
+    }
+    Commands::Diff(diff) => match diff_files(&diff) {
+      Ok(output) => print!("{}", output),
+      Err(why) => {
+        println!("{}", why);
+        std::process::exit(1);
+      }
+    },
+    Commands::Edit(file) => match retrieve_encryption_key(Some(&file.file_name)) {
       Ok(key) => {
-        let fc = FileEncryption::new(file.file_name, key);
+        let mut fc = FileEncryption::new(file.file_name, key)
+          .with_compression(file.compression, file.compression_threshold);
+
+        if let Some(cipher) = file.cipher {
+          fc = fc.with_cipher(cipher);
+        }
+
+        if file.record_key_id {
+          fc = fc.record_key_id();
+        }
+
         let result = fc.edit();
 
         match result {
@@ -44,27 +309,520 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
       Err(why) => println!("{}", why),
     },
-    Commands::Init(..) => match FileEncryption::create("./") {
-      Ok(_) => {}
-      Err(why) => println!("{}", why),
+    Commands::Encrypt(encrypt) => {
+      if let Err(why) = encrypt_stdin(&encrypt) {
+        eprintln!("{}", why);
+        std::process::exit(1);
+      }
+    }
+    Commands::Init(init) => {
+      let result = init_options(&init, cli.key_file.as_deref())
+        .and_then(|options| FileEncryption::create_with("./", &options));
+
+      match result {
+        Ok(created_files) => print_created_files(&created_files),
+        Err(why) => println!("{}", why),
+      }
+    }
+    Commands::Git(git) => match git.command {
+      GitCommands::Clean(clean) => {
+        if let Err(why) = clean_file(&clean.file_name) {
+          eprintln!(
+            "street-cred: could not encrypt {}: {}",
+            clean.file_name, why
+          );
+          std::process::exit(1);
+        }
+      }
+      GitCommands::Hook(hook) => match hook.command {
+        GitHookCommands::Install => {
+          let result = GitSetup::repository_root(Path::new(".")).and_then(|repository| {
+            GitSetup::install_pre_commit_hook(&repository, &driver_command("scan --staged"))
+          });
+
+          match result {
+            Ok(hook_path) => println!("      create  {}", hook_path.display()),
+            Err(why) => {
+              eprintln!("{}", why);
+              std::process::exit(1);
+            }
+          }
+        }
+      },
+      GitCommands::Merge(merge) => {
+        let result = retrieve_encryption_key(merge.path.as_deref())
+          .and_then(|key| GitMerge::merge_files(&merge.base, &merge.ours, &merge.theirs, &key));
+
+        match result {
+          Ok(0) => {}
+          Ok(conflicts) => {
+            eprintln!(
+              "street-cred: {} conflict(s) left, resolve them with street-cred edit",
+              conflicts
+            );
+            std::process::exit(1);
+          }
+          Err(why) => {
+            eprintln!("street-cred: could not merge: {}", why);
+            std::process::exit(1);
+          }
+        }
+      }
+      GitCommands::Setup(setup) => {
+        let result = GitSetup::repository_root(Path::new(".")).and_then(|repository| {
+          let textconv = driver_command("git textconv");
+          let merge = driver_command("git merge %O %A %B %P");
+          let mut added = GitSetup::install_textconv(&repository, &setup.pattern, &textconv)?;
+
+          added.extend(GitSetup::install_merge_driver(
+            &repository,
+            &setup.pattern,
+            &merge,
+          )?);
+
+          if let Some(filter) = &setup.filter {
+            added.extend(GitSetup::install_filter(
+              &repository,
+              filter,
+              &driver_command("git clean %f"),
+              &driver_command("git smudge %f"),
+            )?);
+            added.extend(GitSetup::install_textconv(&repository, filter, &textconv)?);
+            added.extend(GitSetup::install_merge_driver(&repository, filter, &merge)?);
+          }
+
+          Ok(added)
+        });
+
+        match result {
+          Ok(added) => {
+            println!("Registered the street-cred diff and merge drivers in the git config");
+
+            for line in added {
+              println!("      append  .gitattributes  {}", line);
+            }
+          }
+          Err(why) => {
+            eprintln!("{}", why);
+            std::process::exit(1);
+          }
+        }
+      }
+      GitCommands::Smudge(smudge) => smudge_file(&smudge.file_name)?,
+      GitCommands::Textconv(textconv) => textconv_file(&textconv.file_name)?,
+    },
+    Commands::Key(key) => match key.command {
+      KeyCommands::Convert(convert) => {
+        if let Err(why) = convert_key(convert) {
+          eprintln!("{}", why);
+          std::process::exit(1);
+        }
+      }
+      KeyCommands::Fingerprint => {
+        match retrieve_encryption_key(None).and_then(|key| CipherGeneration::key_fingerprint(&key))
+        {
+          Ok(fingerprint) => println!("{}", fingerprint),
+          Err(why) => {
+            eprintln!("{}", why);
+            std::process::exit(1);
+          }
+        }
+      }
+      KeyCommands::Generate(generate) => {
+        if let Err(why) = generate_key(generate) {
+          eprintln!("{}", why);
+          std::process::exit(1);
+        }
+      }
+      KeyCommands::Path => match key_lookup().source(None) {
+        Some(KeySource::Environment) => println!("MASTER_KEY environment variable"),
+        Some(KeySource::File(path)) => println!("{}", path.display()),
+        None => {
+          eprintln!("Could not find master key in environment or file.");
+          std::process::exit(1);
+        }
+      },
+      KeyCommands::Verify(verify) => {
+        let result = retrieve_encryption_key(Some(&verify.file_name))
+          .and_then(|key| FileEncryption::new(verify.file_name.clone(), key).decrypt());
+
+        match result {
+          Ok(_) => println!("Master key decrypts {}", verify.file_name),
+          Err(why) => {
+            eprintln!("{}", why);
+            std::process::exit(1);
+          }
+        }
+      }
+    },
+    Commands::Scan(scan) => match scan_files(&scan) {
+      Ok(findings) if findings.is_empty() => {}
+      Ok(findings) => {
+        for finding in &findings {
+          println!("{}", finding);
+        }
+
+        println!();
+        println!(
+          "Found {} file(s) that should never be committed. Add them to .gitignore, or unstage them with `git rm --cached`.",
+          findings.len()
+        );
+        std::process::exit(1);
+      }
+      Err(why) => {
+        eprintln!("{}", why);
+        std::process::exit(1);
+      }
     },
+    Commands::Show(show) => {
+      let result = retrieve_encryption_key(Some(&show.file_name)).and_then(|key| {
+        let contents = FileEncryption::new(show.file_name.clone(), key.clone()).decrypt()?;
+
+        match show.redact {
+          Some(redaction) => redaction.redact(&contents, Some(&key)),
+          None => Ok(contents),
+        }
+      });
+
+      match result {
+        Ok(contents) => print!("{}", contents),
+        Err(why) => {
+          println!("{}", why);
+          std::process::exit(1);
+        }
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Encrypts stdin with the master key and prints it as "message--iv--tag".
+fn encrypt_stdin(encrypt: &Encrypt) -> anyhow::Result<()> {
+  let key = retrieve_encryption_key(None)?;
+
+  if encrypt.stream {
+    let mut encryptor = StreamEncryptor::new(std::io::stdout().lock(), &key);
+
+    if let Some(cipher) = encrypt.cipher {
+      encryptor = encryptor.with_cipher(cipher);
+    }
+
+    std::io::copy(&mut std::io::stdin().lock(), &mut encryptor)?;
+    encryptor.finish()?.flush()?;
+
+    return Ok(());
+  }
+
+  let mut message = Vec::new();
+
+  std::io::stdin().read_to_end(&mut message)?;
+  std::str::from_utf8(&message)
+    .context("Only UTF-8 text can be encrypted, use --stream for binary data")?;
+
+  let mut encryptor = MessageEncryption::new(message, &key, "");
+
+  if let Some(cipher) = encrypt.cipher {
+    encryptor = encryptor.with_cipher(cipher);
+  }
+
+  println!("{}", encryptor.encrypt()?);
+
+  Ok(())
+}
+
+/// Decrypts a "message--iv--tag" from stdin with the master key and writes the
+/// plaintext to stdout as is. Messages serialized with Marshal or JSON, with or
+/// without Rails' metadata envelope, are accepted. With --stream, decrypts a chunked binary stream
+/// instead.
+fn decrypt_stdin(decrypt: &Decrypt) -> anyhow::Result<()> {
+  let key = retrieve_encryption_key(None)?;
+
+  if decrypt.stream {
+    let mut decryptor = StreamDecryptor::new(std::io::stdin().lock(), &key);
+
+    std::io::copy(&mut decryptor, &mut std::io::stdout().lock())?;
+
+    return Ok(());
+  }
+
+  let mut input = String::new();
+
+  std::io::stdin()
+    .read_to_string(&mut input)
+    .context("Encrypted contents are not valid UTF-8")?;
+
+  let split_contents = MessageEncryption::split_encrypted_contents(input.trim())?;
+  let mut decryptor = MessageEncryption::new(split_contents[0].as_bytes().to_vec(), &key, "");
+
+  if let Some(cipher) = split_contents.get(3) {
+    decryptor = decryptor.with_cipher(cipher.parse()?);
+  }
+
+  let plaintext = decryptor.decrypt_for(split_contents[1], split_contents[2], None)?;
+
+  std::io::stdout().write_all(plaintext.as_bytes())?;
+
+  Ok(())
+}
+
+/// Decrypts both sides of a diff and compares their plaintexts.
+fn diff_files(diff: &Diff) -> anyhow::Result<String> {
+  let key = retrieve_encryption_key(Some(&diff.file_names[0]))?;
+  let decrypt = |file_name: &str, contents: &[u8]| {
+    FileEncryption::new(file_name.to_string(), key.clone())
+      .decrypt_contents(std::str::from_utf8(contents)?)
+      .with_context(|| format!("Could not decrypt {}", file_name))
+  };
+
+  let (old_label, old, new_label, new) = match (&diff.rev, diff.file_names.as_slice()) {
+    (Some(revision), [file_name]) => {
+      let old = GitRevision::contents(Path::new("."), revision, Path::new(file_name))?;
+      let old = decrypt(file_name, &old)?;
+      let current = std::fs::read(file_name)?;
+      let new = match decrypt(file_name, &current) {
+        Ok(new) => new,
+        Err(_) => {
+          String::from_utf8(current).with_context(|| format!("Could not decrypt {}", file_name))?
+        }
+      };
+
+      (
+        format!("{} ({})", file_name, revision),
+        old,
+        file_name.clone(),
+        new,
+      )
+    }
+    (None, [old_name, new_name]) => (
+      old_name.clone(),
+      decrypt(old_name, &std::fs::read(old_name)?)?,
+      new_name.clone(),
+      decrypt(new_name, &std::fs::read(new_name)?)?,
+    ),
+    (Some(_), _) => return Err(anyhow!("--rev compares a single file with its revision")),
+    (None, _) => return Err(anyhow!("Give two files to compare, or one with --rev")),
+  };
+
+  if diff.yaml {
+    PlaintextDiff::yaml(&old, &new, diff.mask)
+  } else {
+    Ok(PlaintextDiff::unified(&old_label, &old, &new_label, &new))
+  }
+}
+
+fn init_options(init: &Init, key_file: Option<&Path>) -> anyhow::Result<CreateOptions> {
+  let key_file = key_file.unwrap_or(Path::new("master.key"));
+  let mut options = CreateOptions::new()
+    .with_cipher(init.cipher)
+    .with_file_name(&init.file)
+    .with_key_file_name(&key_file.to_string_lossy());
+
+  if init.rails_template {
+    options = options.with_rails_template();
+  }
+
+  if let Some(template_path) = &init.template {
+    let template = std::fs::read_to_string(template_path)
+      .with_context(|| format!("Could not read template {}", template_path.display()))?;
+
+    options = options.with_template(&template);
+  }
+
+  if init.no_gitignore {
+    options = options.without_gitignore();
+  }
+
+  Ok(options)
+}
+
+fn print_created_files(created_files: &CreatedFiles) {
+  let key_name = created_files
+    .key_path
+    .file_name()
+    .unwrap_or_default()
+    .to_string_lossy();
+  let key = std::fs::read_to_string(&created_files.key_path).unwrap_or_default();
+
+  println!("Adding {} to store the encryption key: {}", key_name, key);
+  println!();
+  println!("Save this in a password manager your team can access.");
+  println!();
+  println!("If you lose the key, no one, including you, can access anything encrypted with it.");
+  println!();
+  println!("      create  {}", created_files.key_path.display());
+  println!(
+    "      create  {}",
+    created_files.encrypted_file_path.display()
+  );
+
+  if !created_files.ignored_entries.is_empty() {
+    println!();
+    println!(
+      "Ignoring {} so it won't end up in Git history:",
+      created_files.ignored_entries.join(" and ")
+    );
+    println!();
+    println!("      append  .gitignore");
+  }
+
+  println!();
+  println!("Never commit {} to your repository.", key_name);
+}
+
+/// Prints the plaintext of an encrypted file for git. Without a working key,
+/// the file is printed as is, so diffs still work for people without the key.
+/// Files git already decrypted with the smudge filter are printed as is too.
+fn textconv_file(file_name: &str) -> anyhow::Result<()> {
+  let contents = std::fs::read(file_name)?;
+  let looks_encrypted = std::str::from_utf8(&contents)
+    .is_ok_and(|contents| MessageEncryption::split_encrypted_contents(contents.trim()).is_ok());
+  let decrypted = retrieve_encryption_key(Some(file_name))
+    .and_then(|key| FileEncryption::new(file_name.to_string(), key).decrypt());
+
+  match decrypted {
+    Ok(contents) => std::io::stdout().write_all(contents.as_bytes())?,
+    Err(why) => {
+      if looks_encrypted {
+        eprintln!("street-cred: could not decrypt {}: {}", file_name, why);
+      }
+
+      std::io::stdout().write_all(&contents)?;
+    }
   }
 
   Ok(())
 }
 
-fn retrieve_encryption_key() -> anyhow::Result<String> {
-  if let Ok(key) = std::env::var("MASTER_KEY") {
-    return Ok(key);
+/// Scans the given paths, or the files git could commit, for keys and plaintext
+/// secrets.
+fn scan_files(scan: &Scan) -> anyhow::Result<Vec<ScanFinding>> {
+  match scan.staged {
+    true => GitScan::staged(Path::new("."), &key_lookup()),
+    false => GitScan::paths(Path::new("."), &scan.paths, &key_lookup()),
   }
+}
 
-  let key_file_path = std::path::Path::new("master.key");
+/// Encrypts a working file on stdin for git. Failing makes git refuse to store
+/// the file, rather than storing its plaintext.
+fn clean_file(file_name: &str) -> anyhow::Result<()> {
+  let mut contents = Vec::new();
 
-  if key_file_path.exists() {
-    let key = std::fs::read_to_string(key_file_path)?;
+  std::io::stdin().read_to_end(&mut contents)?;
 
-    return Ok(key);
+  let encrypted = GitFilter::clean(
+    file_name,
+    &retrieve_encryption_key(Some(file_name))?,
+    &contents,
+  )?;
+
+  std::io::stdout().write_all(&encrypted)?;
+
+  Ok(())
+}
+
+/// Decrypts a file stored by git on stdin. Without a working key, the encrypted
+/// contents are checked out as is, so people without the key can still clone.
+fn smudge_file(file_name: &str) -> anyhow::Result<()> {
+  let mut contents = Vec::new();
+
+  std::io::stdin().read_to_end(&mut contents)?;
+
+  let decrypted = retrieve_encryption_key(Some(file_name))
+    .and_then(|key| GitFilter::smudge(file_name, &key, &contents));
+
+  match decrypted {
+    Ok(decrypted) => std::io::stdout().write_all(&decrypted)?,
+    Err(why) => {
+      eprintln!("street-cred: could not decrypt {}: {}", file_name, why);
+      std::io::stdout().write_all(&contents)?;
+    }
   }
 
-  Err(anyhow!("Could not find master key in environment or file."))
+  Ok(())
+}
+
+fn convert_key(convert: KeyConvert) -> anyhow::Result<()> {
+  let encoded = match convert.key.as_deref() {
+    Some("-") => {
+      let mut input = Vec::new();
+
+      std::io::stdin().read_to_end(&mut input)?;
+
+      input
+    }
+    Some(key) => key.as_bytes().to_vec(),
+    None => retrieve_encryption_key(None)?.into_bytes(),
+  };
+  let key = convert.from.decode(&encoded)?;
+  let mut output = convert.to.encode(&key);
+
+  if convert.to != KeyFormat::Raw {
+    output.push(b'\n');
+  }
+
+  std::io::stdout().write_all(&output)?;
+
+  Ok(())
+}
+
+fn generate_key(generate: KeyGenerate) -> anyhow::Result<()> {
+  let key = match generate.bits {
+    256 => CipherGeneration::random_key_for(Cipher::Aes256Gcm),
+    _ => CipherGeneration::random_key(),
+  };
+
+  match generate.out {
+    Some(path) if path.exists() => Err(anyhow!(
+      "{} already exists, refusing to overwrite it.",
+      path.display()
+    )),
+    Some(path) => FileEncryption::write_key_file(&path, &key),
+    None => {
+      println!("{}", key);
+
+      Ok(())
+    }
+  }
+}
+
+/// Finds keys, starting with the --key-file option.
+fn key_lookup() -> KeyLookup {
+  match KEY_FILE.get() {
+    Some(key_file) => KeyLookup::new().with_key_file(key_file),
+    None => KeyLookup::new(),
+  }
+}
+
+fn retrieve_encryption_key(file_name: Option<&str>) -> anyhow::Result<String> {
+  key_lookup().key(file_name)
+}
+
+/// Returns the command git runs to call street-cred with `arguments`, passing
+/// on the --key-file option. The running executable is called by its absolute
+/// path, so git finds it even when it isn't on the PATH, e.g. when installed
+/// under a different name or run from a build directory.
+fn driver_command(arguments: &str) -> String {
+  let executable = std::env::current_exe()
+    .map(|executable| shell_quote(&executable))
+    .unwrap_or_else(|_| "street-cred".to_string());
+
+  match KEY_FILE.get() {
+    Some(key_file) => {
+      let key_file = std::fs::canonicalize(key_file).unwrap_or(key_file.clone());
+
+      format!(
+        "{} --key-file {} {}",
+        executable,
+        shell_quote(&key_file),
+        arguments
+      )
+    }
+    None => format!("{} {}", executable, arguments),
+  }
+}
+
+/// Quotes a path for the shell git runs drivers and hooks with.
+fn shell_quote(path: &Path) -> String {
+  format!("'{}'", path.to_string_lossy().replace('\'', "'\\''"))
 }
@@ -1,3 +1,5 @@
+mod redaction;
 mod ruby_marshal;
 
+pub use self::redaction::Redaction;
 pub use self::ruby_marshal::RubyMarshal;